@@ -1,17 +1,126 @@
+use crate::{
+    config::{Priority, Severity},
+    error::ErrorDetail,
+};
 use clap::{Parser, Subcommand, ValueEnum};
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf};
+
+impl Cli {
+    /// The config file commands which write config should write to.
+    pub fn config_file(&self) -> &PathBuf {
+        self.config.last().expect("`--config` has a default")
+    }
+
+    /// The message given as arguments, each on its own line.
+    pub fn message(&self) -> Option<String> {
+        (!self.message.is_empty()).then(|| self.message.join("\n"))
+    }
+}
 
 /// Send desktop and webhook notifications from the command line.
 #[derive(Debug, Parser)]
 #[clap(version, name = "noti")]
 pub struct Cli {
     /// The message to send when using as `cmd && noti 'Message'`.
+    ///
+    /// Several are sent as one message, each on its own line.
     #[arg()]
-    pub message: Option<String>,
+    pub message: Vec<String>,
 
-    /// The path to the config to use.
+    /// The path to the config to use. Without it, `noti.yaml` is used if it exists,
+    /// and otherwise `$XDG_CONFIG_HOME/noti/config.yaml` or `~/.config/noti/config.yaml`.
+    ///
+    /// Repeat to merge several configs, later ones taking precedence.
+    /// Commands which write config write to the last one.
     #[arg(long, default_value = "noti.yaml", env = "NOTI_CONFIG")]
-    pub config: PathBuf,
+    pub config: Vec<PathBuf>,
+
+    /// Read the whole of stdin and send it as a single message once stdin closes.
+    #[arg(long)]
+    pub stdin: bool,
+
+    /// Send the whole of this file as the message, or of stdin if `-`.
+    ///
+    /// It's used instead of `message` if both are given, and can't be used when streaming.
+    #[arg(long, value_name = "PATH", conflicts_with = "stdin")]
+    pub message_file: Option<PathBuf>,
+
+    /// Stream stdin, but send every matching line as one message once stdin closes.
+    #[arg(long, conflicts_with = "stdin")]
+    pub buffer_all: bool,
+
+    /// Stream stdin as JSON lines, forwarding each one as the body of webhook requests.
+    #[arg(long, conflicts_with = "stdin")]
+    pub input_json_line: bool,
+
+    /// Stream lines appended to this file or named pipe instead of stdin, reopening
+    /// it when it's rotated.
+    #[arg(long, value_name = "PATH", conflicts_with = "stdin")]
+    pub follow: Option<PathBuf>,
+
+    /// The most bytes of stdin to send when using `--stdin`, the rest is dropped.
+    #[arg(long, default_value_t = 65536)]
+    pub max_stdin_bytes: u64,
+
+    /// Only send to the destination with this `name`. Repeat to send to several.
+    #[arg(long, value_name = "NAME")]
+    pub to: Vec<String>,
+
+    /// How important the notification is.
+    #[arg(long, value_enum)]
+    pub priority: Option<Priority>,
+
+    /// How the outcome is shown by formats which color it, such as `discord_embed`,
+    /// rather than guessing from the message. Also substituted for `$(severity)`.
+    #[arg(long, value_enum)]
+    pub severity: Option<Severity>,
+
+    /// Fail if the config contains keys noti doesn't recognise.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// What to do when the config has no destinations.
+    #[arg(long, value_enum, default_value = "warn")]
+    pub on_empty: OnEmpty,
+
+    /// Print what would be sent to each destination instead of sending it.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Show only the length of messages in noti's own output, such as `--dry-run`,
+    /// while still sending them in full.
+    #[arg(long)]
+    pub mask_message: bool,
+
+    /// Print each destination's average and maximum send latency before exiting.
+    #[arg(long)]
+    pub summary: bool,
+
+    /// Show a desktop notification if the config can't be parsed.
+    #[arg(long, env = "NOTI_DESKTOP_ON_INVALID_CONFIG")]
+    pub desktop_on_invalid_config: bool,
+
+    /// How noti reports what it did.
+    #[arg(long, value_enum, default_value = "text")]
+    pub output: Output,
+
+    /// Log each send, response status and retry to stderr. Repeat for more detail,
+    /// such as each message sent.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Whether errors include the underlying errors which caused them.
+    #[arg(long, value_enum, default_value = "short")]
+    pub error_detail: ErrorDetail,
+
+    /// Exit code of the command being notified about, substituted for `$(exit_code)`
+    /// in the message. A nonzero code makes desktop notifications critical.
+    #[arg(long, value_name = "N", allow_negative_numbers = true)]
+    pub exit_code: Option<i32>,
+
+    /// Serve JSON status counters over HTTP on this address while running.
+    #[arg(long, value_name = "ADDR")]
+    pub serve_status: Option<SocketAddr>,
 
     /// Optional subcommands.
     #[command(subcommand)]
@@ -27,6 +136,36 @@ pub enum Command {
         /// Initialise a custom webhook destination. Has no effect on desktop destination.
         #[arg(long)]
         custom: bool,
+        /// Send a test notification once the config is written.
+        #[arg(long)]
+        test: bool,
+    },
+    /// Send a test notification to every configured destination.
+    Test,
+    /// Check the config for problems without sending anything.
+    Validate,
+    /// Show what each line of a sample file would send when streamed, without sending it.
+    Preview {
+        /// File of sample input lines.
+        #[arg(long)]
+        sample: PathBuf,
+    },
+    /// Print version and build information.
+    Version {
+        /// Print as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Run a command, then send a notification saying how it went and how long it took.
+    Run {
+        /// The command to run and its arguments, after `--`.
+        #[arg(trailing_var_arg = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Print a completion script for `shell`.
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
     },
     /// Commands about supported notification destinations.
     Destination {
@@ -35,12 +174,41 @@ pub enum Command {
     },
 }
 
+/// How noti reports what it did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// Print errors as text.
+    Text,
+    /// Print a JSON object with how each send went, and any error.
+    Json,
+}
+
+/// How to treat a config without any destinations.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OnEmpty {
+    /// Print a warning and carry on.
+    Warn,
+    /// Fail with an error.
+    Error,
+}
+
 #[derive(Debug, Clone, ValueEnum)]
 pub enum DestinationType {
     /// Create a new `noti.yaml` file for desktop notifications.
     Desktop,
     /// Create a new `noti.yaml` file for webhook notifications.
     Webhook,
+    /// Create a new `noti.yaml` file for Slack webhook notifications.
+    Slack,
+    /// Create a new `noti.yaml` file for Mattermost webhook notifications.
+    Mattermost,
+    /// Create a new `noti.yaml` file for Telegram bot notifications.
+    Telegram,
+    /// Create a new `noti.yaml` file for email notifications over SMTP.
+    Email,
+    /// Create a new `noti.yaml` file for PagerDuty incidents.
+    #[value(name = "pagerduty")]
+    PagerDuty,
 }
 
 #[derive(Debug, Subcommand)]
@@ -1,57 +1,775 @@
 use crate::{
-    cli::{Cli, DestinationCommand, DestinationType},
-    config::{AsHeaderMap, Config, Destination, Redirect, WebhookFormat},
+    cli::{Cli, DestinationCommand, DestinationType, OnEmpty},
+    config::{
+        AsHeaderMap, Config, ConfigFormat, CustomWebhookFormat, DesktopBackend, Destination,
+        DestinationKind, DryRunLog, Email, HttpMethod, Matching, OnError, Overflow,
+        PagerDutySeverity, Priority, Redirect, RequestOptions, Severity, SmtpTls, WebhookFormat,
+    },
     error::{Error, Result},
+    status::{self, Status},
+    template::{self, Vars},
+    throttle,
+};
+use clap::CommandFactory;
+use futures::{StreamExt, TryStreamExt};
+use indexmap::IndexMap;
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncTransport,
 };
 use regex::Regex;
 use std::{
-    io::{self, BufRead},
-    path::PathBuf,
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    ffi::OsString,
+    io::{self, BufRead, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
-use tokio::fs;
+use tokio::{fs, io::AsyncWriteExt, sync::mpsc};
+
+/// Where the Telegram Bot API is hosted.
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+/// Where PagerDuty Events API v2 events are sent.
+const PAGERDUTY_EVENTS_API: &str = "https://events.pagerduty.com/v2/enqueue";
+
+/// SMTP transport used by email destinations.
+type SmtpTransport = lettre::AsyncSmtpTransport<lettre::Tokio1Executor>;
+
+/// Longest chain of fallback destinations tried for a single message.
+const MAX_FALLBACKS: usize = 3;
+
+/// Longest a crash notification may take before noti gives up on it.
+const CRASH_NOTIFY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Message sent to destinations by the `test` command.
+const TEST_MESSAGE: &str = "Test notification from noti.";
+
+/// Most throttled stream messages held before they're sent early.
+const MAX_COALESCED_MESSAGES: usize = 100;
+
+/// How long a partial stream batch waits for another message before it's sent.
+const DEFAULT_BATCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long an `exec` destination's command may run when it doesn't set `timeout_secs`.
+const DEFAULT_EXEC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How many lines of stream input are read ahead of dispatching.
+const READ_AHEAD_LINES: usize = 1024;
+
+/// Build an HTTP client which gives up on requests after `timeout`, if set.
+///
+/// Requests go through the proxy in `options`, with `${NAME}` environment variables
+/// expanded, or else through the one in the environment. Certificates are checked
+/// against the system's authorities, and the one in `options.tls` if set.
+fn http_client(timeout: Option<Duration>, options: &RequestOptions) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(tls) = &options.tls {
+        if let Some(path) = &tls.ca_cert {
+            let pem = std::fs::read(path).map_err(|source| Error::CaCert {
+                path: path.clone(),
+                source,
+            })?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        builder = builder.danger_accept_invalid_certs(tls.accept_invalid_certs);
+    }
+    if let Some(proxy) = &options.proxy {
+        let proxy =
+            reqwest::Proxy::all(template::expand_env(proxy, |name| options.env.var(name))?)?
+                .no_proxy(reqwest::NoProxy::from_env());
+        builder = builder.proxy(proxy);
+    }
+    if !options.follow_redirects {
+        builder = builder.redirect(reqwest::redirect::Policy::none());
+    }
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    Ok(builder.build()?)
+}
+
+/// Describe `error`, with any URL in it shortened to its origin as it may embed secrets.
+fn redact_url(error: &reqwest::Error) -> String {
+    let description = error.to_string();
+    match error.url() {
+        Some(url) => description.replace(
+            url.as_str(),
+            &format!("{}/***", url.origin().ascii_serialization()),
+        ),
+        None => description,
+    }
+}
+
+/// The shortest time waited before resending a rate limited request.
+const MIN_RATE_LIMIT_WAIT: Duration = Duration::from_millis(100);
+
+/// How long a response asks to be waited before retrying, if it says.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?;
+    let secs = value.to_str().ok()?.trim().parse().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// How long a 429 response asks to be waited, from its headers or body.
+async fn rate_limit_wait(response: reqwest::Response) -> Option<Duration> {
+    if let Some(wait) = retry_after(&response) {
+        return Some(wait);
+    }
+
+    // Discord gives fractional seconds as `retry_after` in a JSON body.
+    let body: serde_json::Value = serde_json::from_str(&response.text().await.ok()?).ok()?;
+    Duration::try_from_secs_f64(body["retry_after"].as_f64()?).ok()
+}
+
+/// Send `request`, retrying failures according to `options`.
+///
+/// A 429 which says how long to wait is always waited out and resent, for up
+/// to `max_rate_limit_wait` in total. Otherwise, timeouts, 429s and 5xx
+/// responses are retried with exponential backoff if `retry` is set, waiting
+/// as long as a `Retry-After` header asks instead when present. Any other
+/// failure is returned immediately. How many attempts were made and how long
+/// was spent waiting between them is logged at the end.
+async fn send(
+    request: reqwest::RequestBuilder,
+    options: &RequestOptions,
+) -> Result<reqwest::Response> {
+    let retry = options.retry.as_ref();
+    let max_attempts = retry.map_or(1, |retry| retry.max_attempts.max(1));
+    let mut delay = retry.map_or(Duration::ZERO, |retry| {
+        Duration::from_millis(retry.initial_delay_ms)
+    });
+    let mut attempt = 1;
+    let mut rate_limited = Duration::ZERO;
+    let mut backoff = Duration::ZERO;
+    let summary = |attempt: u32, backoff: Duration| {
+        let attempts = match attempt {
+            1 => "1 attempt".to_string(),
+            n => format!("{n} attempts"),
+        };
+        tracing::info!("Finished after {attempts}, waiting {backoff:?} in total");
+    };
+
+    loop {
+        let request = request
+            .try_clone()
+            .expect("Request bodies are always buffered");
+        let retrying = attempt < max_attempts;
+
+        let result = request.send().await;
+        match &result {
+            Ok(response) => tracing::info!("Got response {}", response.status()),
+            Err(e) => tracing::info!("Request failed: {}", redact_url(e)),
+        }
+
+        let wait = match result {
+            Ok(response) if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                match rate_limit_wait(response).await {
+                    Some(wait) => {
+                        let wait = wait.max(MIN_RATE_LIMIT_WAIT);
+                        if rate_limited + wait > options.max_rate_limit_wait {
+                            summary(attempt, backoff);
+                            return Err(Error::RateLimited);
+                        }
+                        rate_limited += wait;
+                        tracing::info!("Rate limited, resending in {wait:?}");
+                        tokio::time::sleep(wait).await;
+                        backoff += wait;
+                        continue;
+                    }
+                    None if retrying => delay,
+                    None => {
+                        summary(attempt, backoff);
+                        return Err(Error::RateLimited);
+                    }
+                }
+            }
+            Ok(response) if retrying && response.status().is_server_error() => {
+                retry_after(&response).unwrap_or(delay)
+            }
+            Err(e) if retrying && e.is_timeout() => delay,
+            Ok(response) if !options.follow_redirects && response.status().is_redirection() => {
+                summary(attempt, backoff);
+                return Err(Error::Redirected {
+                    status: response.status().as_u16(),
+                });
+            }
+            result => {
+                summary(attempt, backoff);
+                return Ok(result?.error_for_status()?);
+            }
+        };
+
+        tracing::info!(
+            "Retrying in {wait:?}, attempt {} of {max_attempts}",
+            attempt + 1
+        );
+        tokio::time::sleep(wait).await;
+        backoff += wait;
+        delay *= 2;
+        attempt += 1;
+    }
+}
 
 /// Send a message over webhook.
-async fn dispatch_webhook(message: &str, url: &str, format: &WebhookFormat) -> Result<()> {
-    let client = reqwest::Client::builder().build()?;
+///
+/// `${NAME}` environment variables in `url` are expanded just before sending.
+/// A custom format's own timeout takes precedence over the one in `options`.
+async fn dispatch_webhook(
+    message: &str,
+    vars: &Vars,
+    url: &str,
+    format: &WebhookFormat,
+    title: Option<&str>,
+    options: &RequestOptions,
+) -> Result<()> {
+    let timeout = match format {
+        WebhookFormat::Custom(fmt) => fmt
+            .timeout_secs
+            .map(Duration::from_secs)
+            .or(options.timeout),
+        _ => options.timeout,
+    };
+    let client = http_client(timeout, options)?;
+    let url = template::expand_env(url, |name| options.env.var(name))?;
+    let gzip = matches!(format, WebhookFormat::Custom(fmt) if fmt.gzip);
 
     let resp = match format {
-        WebhookFormat::Custom(fmt) => client
-            .request(fmt.http.method.clone().into(), url)
-            .headers(fmt.http.headers.as_header_map()?)
-            .body(format.format_message(message)),
+        _ if options.raw_json => {
+            let request = match format {
+                WebhookFormat::Custom(fmt) => {
+                    let request = client
+                        .request(fmt.http.method.clone().into(), url)
+                        .headers(fmt.http.headers.as_header_map()?);
+                    match &fmt.auth {
+                        Some(auth) => auth.apply(request, |name| options.env.var(name))?,
+                        None => request,
+                    }
+                }
+                _ => client.post(url),
+            };
+            with_body(
+                request.header(reqwest::header::CONTENT_TYPE, "application/json"),
+                message.to_string(),
+                gzip,
+            )?
+        }
+        WebhookFormat::Custom(fmt) => {
+            let url = match fmt.http.method {
+                HttpMethod::GET => {
+                    let query =
+                        format_webhook_message(message, title, vars, format, options).await?;
+                    match url.contains('?') {
+                        true => format!("{url}&{query}"),
+                        false => format!("{url}?{query}"),
+                    }
+                }
+                _ => url,
+            };
+            let mut request = client
+                .request(fmt.http.method.clone().into(), url)
+                .headers(fmt.http.headers.as_header_map()?);
+            if !matches!(fmt.http.method, HttpMethod::HEAD | HttpMethod::GET) {
+                let body = format_webhook_message(message, title, vars, format, options).await?;
+                request = with_body(request, body, gzip)?;
+            }
+            match &fmt.auth {
+                Some(auth) => auth.apply(request, |name| options.env.var(name))?,
+                None => request,
+            }
+        }
         _ => client
             .post(url)
-            .header(reqwest::header::CONTENT_TYPE, format.as_content_type())
-            .body(format.format_message(message)),
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                format.as_content_type(options.charset.as_deref()),
+            )
+            .body(format_webhook_message(message, title, vars, format, options).await?),
     };
 
-    resp.send().await?.error_for_status()?;
+    if options.dry_run {
+        return print_dry_run(resp, options);
+    }
+    let response = send(resp, options).await?;
+    if let WebhookFormat::Custom(CustomWebhookFormat {
+        response_log: Some(log),
+        ..
+    }) = format
+    {
+        eprintln!("{}", response_log(log, &response));
+    }
+    Ok(())
+}
+
+/// Format `message` for `format`, on a blocking thread if the template may run
+/// `$(exec:...)` commands, which block until they finish or time out.
+async fn format_webhook_message(
+    message: &str,
+    title: Option<&str>,
+    vars: &Vars,
+    format: &WebhookFormat,
+    options: &RequestOptions,
+) -> Result<String> {
+    let runs_exec = options.allow_exec
+        && matches!(format, WebhookFormat::Custom(fmt) if fmt.template.contains("$(exec:"));
+    if !runs_exec {
+        return format.format_message(message, title, vars, options.allow_exec);
+    }
+
+    let (message, title, vars, format) = (
+        message.to_string(),
+        title.map(String::from),
+        vars.clone(),
+        format.clone(),
+    );
+    tokio::task::spawn_blocking(move || {
+        format.format_message(&message, title.as_deref(), &vars, true)
+    })
+    .await
+    .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+}
+
+/// Set the body of `request`, compressing it with gzip when `gzip` is set.
+fn with_body(
+    request: reqwest::RequestBuilder,
+    body: String,
+    gzip: bool,
+) -> Result<reqwest::RequestBuilder> {
+    if !gzip {
+        return Ok(request.body(body));
+    }
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    io::Write::write_all(&mut encoder, body.as_bytes())?;
+    Ok(request
+        .header(reqwest::header::CONTENT_ENCODING, "gzip")
+        .body(encoder.finish()?))
+}
+
+/// Render a custom format's `response_log`, substituting `$(header:<name>)`
+/// with the value of the response header `name`.
+fn response_log(template: &str, response: &reqwest::Response) -> String {
+    template::substitute(template, |name| {
+        let header = response.headers().get(name.strip_prefix("header:")?)?;
+        Some(String::from_utf8_lossy(header.as_bytes()).into_owned())
+    })
+}
+
+/// Send a message to a Telegram chat through the Bot API.
+async fn dispatch_telegram(
+    message: &str,
+    token: &str,
+    chat_id: &str,
+    options: &RequestOptions,
+) -> Result<()> {
+    send_telegram(TELEGRAM_API, message, token, chat_id, options).await
+}
+
+/// Trigger a PagerDuty incident summarised by the message.
+async fn dispatch_pagerduty(
+    message: &str,
+    routing_key: &str,
+    severity: PagerDutySeverity,
+    options: &RequestOptions,
+) -> Result<()> {
+    let routing_key = template::expand_env(routing_key, |name| options.env.var(name))?;
+    send_pagerduty(
+        PAGERDUTY_EVENTS_API,
+        message,
+        &routing_key,
+        severity,
+        options,
+    )
+    .await
+}
+
+/// Write a message to the file at `path`, creating it and its parent directories
+/// if needed.
+///
+/// The entry is rendered from `format`, which can use stream captures and the
+/// built-in placeholders, and defaults to `$(timestamp) $(message)`.
+async fn dispatch_file(
+    message: &str,
+    vars: &Vars,
+    path: &Path,
+    append: bool,
+    format: Option<&str>,
+) -> Result<()> {
+    let entry = template::substitute(
+        format.unwrap_or("$(timestamp) $(message)"),
+        |name| match name {
+            "message" => Some(message.to_string()),
+            _ => vars
+                .get(name)
+                .cloned()
+                .or_else(|| template::numeric(name, vars))
+                .or_else(|| template::builtin(name)),
+        },
+    );
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).await.map_err(Error::Io)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .await
+        .map_err(Error::Io)?;
+    file.write_all(format!("{entry}\n").as_bytes()).await?;
+    file.flush().await?;
+    Ok(())
+}
+
+/// Run `command` with `args`, passing the message in the `NOTI_MESSAGE`
+/// environment variable, and fail if it exits unsuccessfully.
+///
+/// `$(message)` and stream captures are substituted in `args`. The command's
+/// output goes to stderr, so it doesn't mix with streamed input on stdout.
+async fn dispatch_exec(
+    message: &str,
+    vars: &Vars,
+    command: &str,
+    args: &[String],
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let mut child = tokio::process::Command::new(command)
+        .args(args.iter().map(|arg| template::render(arg, message, vars)))
+        .env("NOTI_MESSAGE", message)
+        .stdin(std::process::Stdio::null())
+        .stdout(io::stderr())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(Error::Io)?;
+
+    let status = tokio::time::timeout(timeout.unwrap_or(DEFAULT_EXEC_TIMEOUT), child.wait())
+        .await
+        .map_err(|_| Error::Timeout)??;
+    match status.success() {
+        true => Ok(()),
+        false => Err(Error::ExecFailed {
+            command: command.to_string(),
+            code: status.code(),
+        }),
+    }
+}
+
+/// Send a message as an email over SMTP.
+///
+/// Login credentials are read from the environment just before sending.
+async fn dispatch_email(message: &str, email: &Email, options: &RequestOptions) -> Result<()> {
+    let mail = lettre::Message::builder()
+        .from(email.from.parse()?)
+        .to(email.to.parse()?)
+        .subject(&email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(message.to_string())?;
+
+    let mut transport = match email.tls {
+        SmtpTls::StartTls => SmtpTransport::starttls_relay(&email.smtp_host)?,
+        SmtpTls::Tls => SmtpTransport::relay(&email.smtp_host)?,
+        SmtpTls::None => SmtpTransport::builder_dangerous(&email.smtp_host),
+    }
+    .port(email.smtp_port);
+    if let Some(timeout) = options.timeout {
+        transport = transport.timeout(Some(timeout));
+    }
+    if let Some((username, password)) = email.credentials(|name| options.env.var(name))? {
+        transport = transport.credentials(Credentials::new(username, password));
+    }
+
+    transport.build().send(mail).await?;
+    Ok(())
+}
+
+/// Send a message with the Telegram Bot API hosted at `api`.
+async fn send_telegram(
+    api: &str,
+    message: &str,
+    token: &str,
+    chat_id: &str,
+    options: &RequestOptions,
+) -> Result<()> {
+    let body = serde_json::to_string(&serde_json::json!({"chat_id": chat_id, "text": message}))
+        .expect("Serde serialize for `serde_json::json`");
+
+    let request = http_client(options.timeout, options)?
+        .post(format!("{api}/bot{token}/sendMessage"))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body);
+
+    if options.dry_run {
+        return print_dry_run(request, options);
+    }
+    send(request, options).await?;
+    Ok(())
+}
+
+/// Send a `trigger` event to the PagerDuty Events API hosted at `api`.
+///
+/// The incident's source is this machine's hostname.
+async fn send_pagerduty(
+    api: &str,
+    message: &str,
+    routing_key: &str,
+    severity: PagerDutySeverity,
+    options: &RequestOptions,
+) -> Result<()> {
+    let body = serde_json::to_string(&serde_json::json!({
+        "routing_key": routing_key,
+        "event_action": "trigger",
+        "payload": {
+            "summary": message,
+            "severity": severity,
+            "source": gethostname::gethostname().to_string_lossy(),
+        },
+    }))
+    .expect("Serde serialize for `serde_json::json`");
+
+    let request = http_client(options.timeout, options)?
+        .post(api)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body);
+
+    if options.dry_run {
+        return print_dry_run(request, options);
+    }
+    send(request, options).await?;
+    Ok(())
+}
+
+/// Print what `request` would send, instead of sending it.
+fn print_dry_run(request: reqwest::RequestBuilder, options: &RequestOptions) -> Result<()> {
+    log_dry_run(
+        describe_request(&request.build()?, options.mask_message),
+        options,
+    );
     Ok(())
 }
 
+/// Print a dry run's `description`, or add it to `options.dry_run_log` if set.
+fn log_dry_run(description: String, options: &RequestOptions) {
+    match &options.dry_run_log {
+        Some(log) => {
+            let mut log = log.lock().unwrap();
+            let line = log.line;
+            log.sent.push((line, description));
+        }
+        None => println!("{description}"),
+    }
+}
+
+/// Show `text` as it is, or only its length if `mask` is set.
+fn masked(text: &str, mask: bool) -> Cow<'_, str> {
+    match mask {
+        true => format!("[masked, {} bytes]", text.len()).into(),
+        false => text.into(),
+    }
+}
+
+/// Describe the method, URL, content type and body of `request`.
+///
+/// Webhook URLs often embed secrets, so only the origin is shown. The body is
+/// masked if `mask` is set.
+fn describe_request(request: &reqwest::Request, mask: bool) -> String {
+    let url = request.url();
+    let mut description = format!(
+        "Would send {} {}",
+        request.method(),
+        url.origin().ascii_serialization()
+    );
+    if url.path() != "/" || url.query().is_some() {
+        description.push_str("/***");
+    }
+    if let Some(content_type) = request.headers().get(reqwest::header::CONTENT_TYPE) {
+        description.push_str(&format!(
+            "\ncontent-type: {}",
+            String::from_utf8_lossy(content_type.as_bytes())
+        ));
+    }
+    let gzip = request.headers().get(reqwest::header::CONTENT_ENCODING)
+        == Some(&reqwest::header::HeaderValue::from_static("gzip"));
+    if let Some(body) = request.body().and_then(reqwest::Body::as_bytes) {
+        match gzip {
+            true => description.push_str(&format!("\n[gzip, {} bytes]", body.len())),
+            false => {
+                let body = String::from_utf8_lossy(body);
+                description.push_str(&format!("\n{}", masked(&body, mask)));
+            }
+        }
+    }
+    description
+}
+
 /// Send a desktop notification.
-fn dispatch_desktop(message: &str, summary: &str, persistent: bool) -> Result<()> {
+///
+/// Notifications about a command which failed are shown with critical urgency,
+/// where the platform supports it.
+fn dispatch_desktop(
+    message: &str,
+    summary: &str,
+    persistent: bool,
+    icon: Option<&str>,
+    failed: bool,
+    progress: Option<notify_rust::Hint>,
+) -> Result<()> {
     let mut notification = notify_rust::Notification::new()
         .summary(summary)
         .body(message)
         .appname("noti")
         .finalize();
 
+    if let Some(icon) = icon {
+        notification.icon(icon);
+    }
+
     if persistent {
         notification.timeout(0);
     }
 
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if failed {
+        notification.urgency(notify_rust::Urgency::Critical);
+    }
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if let Some(progress) = progress {
+        notification.hint(progress);
+    }
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    let _ = (failed, progress);
+
     notification.show()?;
     Ok(())
 }
 
-/// Dispatch messages by listening to stdin.
+/// The progress hint for a `progress` capture, a percentage like `42` or `42.5%`.
+///
+/// Only some notification servers show it, and the capture is ignored if it isn't numeric.
+fn progress_hint(vars: &Vars) -> Option<notify_rust::Hint> {
+    progress_percent(vars).map(|percent| notify_rust::Hint::CustomInt("value".into(), percent))
+}
+
+/// The `progress` capture as a whole percentage, clamped between 0 and 100.
+fn progress_percent(vars: &Vars) -> Option<i32> {
+    let progress = vars.get("progress")?.trim().trim_end_matches('%');
+    let percent = progress.parse::<f64>().ok().filter(|p| p.is_finite())?;
+    Some(percent.round().clamp(0.0, 100.0) as i32)
+}
+
+/// Dispatch messages by listening to `input`.
 ///
-/// Respects the `stream.matching` config if set by excluding
-/// non-matching lines read from stdin.
-async fn stream_and_dispatch(config: &Config) -> Result<()> {
-    for line in io::stdin().lock().lines() {
+/// Lines read from `input` are filtered by:
+/// - `stream.matching`, excluding lines which don't match. Destinations with a
+///   `matching` of their own use it instead.
+/// - `stream.skip_blank`, excluding blank lines.
+/// - `stream.dedupe`, skipping lines whose dedupe key has already been sent, within
+///   the last `stream.dedupe_ttl_ms` if set.
+///
+/// Messages are rendered with `stream.template`, which along with any captures can
+/// refer to the line before as `$(prev_line)`, the last `stream.context_lines` lines
+/// as `$(prev_lines)`, and the config files in use as `$(config_path)`.
+///
+/// Messages are sent straight away unless held by one of:
+/// - `stream.throttle_ms`, until that long after the first one, at most
+///   [`MAX_COALESCED_MESSAGES`] are waiting, or the input ends, then sent together.
+/// - `stream.batch_size`, until that many are waiting or none has arrived for
+///   `stream.batch_timeout_ms`.
+/// - `stream.buffer_all`, until the input ends.
+/// - `stream.digest_interval_ms`, until the next tick of the interval.
+///
+/// With `stream.heartbeat_secs`, a notification is sent whenever nothing has matched
+/// for that long. With `stream.json_line`, only lines which are JSON objects matching
+/// `stream.json_filter` are sent, each one as it is and straight away.
+async fn stream_and_dispatch(
+    input: impl BufRead + Send + 'static,
+    config: &Config,
+    priority: Option<Priority>,
+    status: &Status,
+) -> Result<()> {
+    let mut seen = HashMap::new();
+    let config_path = config.config_path();
+    let dedupe_ttl = config.stream.dedupe_ttl_ms.map(Duration::from_millis);
+    let context_lines = config.stream.context_lines.unwrap_or(1);
+    let mut history = VecDeque::with_capacity(context_lines);
+    let stream_patterns = config.stream.matching.as_ref().map(compile).transpose()?;
+    let destination_patterns = config
+        .destination
+        .iter()
+        .map(|destination| destination.matching.as_ref().map(compile).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    let window = config.stream.throttle_ms.map(Duration::from_millis);
+    let idle = config.stream.batch_size.map(|_| {
+        config
+            .stream
+            .batch_timeout_ms
+            .map_or(DEFAULT_BATCH_TIMEOUT, Duration::from_millis)
+    });
+    let limit = config
+        .stream
+        .batch_size
+        .map_or(MAX_COALESCED_MESSAGES, |size| size.max(1));
+    let digest = config.stream.digest_interval_ms.map(Duration::from_millis);
+    let holding = !config.stream.json_line
+        && (config.stream.buffer_all || digest.is_some() || window.is_some() || idle.is_some());
+    let mut pending = Pending::new();
+    let mut cap = RateCap::new(config.max_per_minute);
+    let mut pending_count = 0;
+    let mut window_end = None;
+    let mut idle_end = None;
+    let mut digest_end = digest.map(|digest| tokio::time::Instant::now() + digest);
+    let heartbeat = config.stream.heartbeat_secs.map(Duration::from_secs);
+    let mut heartbeat_end = heartbeat.map(|heartbeat| tokio::time::Instant::now() + heartbeat);
+    let mut lines = read_lines(input);
+
+    loop {
+        let timers = [window_end, idle_end, digest_end];
+        let line = match timers.into_iter().chain([heartbeat_end]).flatten().min() {
+            Some(at) => match tokio::time::timeout_at(at, lines.recv()).await {
+                Ok(line) => line,
+                Err(_) => {
+                    let now = tokio::time::Instant::now();
+                    if let Some(heartbeat) = heartbeat.filter(|_| heartbeat_end <= Some(now)) {
+                        let message = format!(
+                            "Still running, nothing matched in the last {}",
+                            format_elapsed(heartbeat)
+                        );
+                        dispatch_target(
+                            None,
+                            &message,
+                            &Vars::new(),
+                            &mut cap,
+                            priority,
+                            config,
+                            status,
+                        )
+                        .await?;
+                        heartbeat_end = Some(now + heartbeat);
+                        if timers.into_iter().flatten().all(|at| at > now) {
+                            continue;
+                        }
+                    }
+                    flush(&mut pending, &mut cap, priority, config, status).await?;
+                    pending_count = 0;
+                    (window_end, idle_end) = (None, None);
+                    digest_end = digest_end.zip(digest).map(|(end, digest)| end + digest);
+                    continue;
+                }
+            },
+            None => lines.recv().await,
+        };
+        let Some(line) = line else {
+            break;
+        };
         let value = line?;
+        status.record_line();
+        if let Some(log) = &config.dry_run_log {
+            log.lock().unwrap().line += 1;
+        }
 
         match config.stream.redirect {
             Some(Redirect::Stderr) => eprintln!("{value}"),
@@ -59,155 +777,3673 @@ async fn stream_and_dispatch(config: &Config) -> Result<()> {
             None => (),
         };
 
-        match &config.stream.matching {
-            Some(pattern) => {
-                let re = Regex::new(pattern)?;
-                let Some(captures) = re.captures(&value) else {
-                    continue;
-                };
+        if config.stream.skip_blank && value.trim().is_empty() {
+            continue;
+        }
+        if config.stream.json_line && !json_line_matches(&value, &config.stream.json_filter) {
+            continue;
+        }
+
+        let prev_line = history.back().cloned().unwrap_or_default();
+        let prev_lines = history.make_contiguous().join("\n");
+        if context_lines > 0 {
+            if history.len() == context_lines {
+                history.pop_front();
+            }
+            history.push_back(value.clone());
+        }
+
+        // Messages along with the destination they're for, or `None` for every
+        // destination without a `matching` of its own.
+        let mut matches = vec![];
+        if destination_patterns.iter().any(Option::is_none) {
+            let found = match &stream_patterns {
+                Some(patterns) => match_line(
+                    &value,
+                    patterns,
+                    config.stream.match_all,
+                    config.stream.global,
+                ),
+                None => vec![(value.clone(), Vars::new())],
+            };
+            matches.extend(found.into_iter().map(|found| (None, found)));
+        }
+        for (index, patterns) in destination_patterns.iter().enumerate() {
+            if let Some(patterns) = patterns {
+                let found = match_line(
+                    &value,
+                    patterns,
+                    config.stream.match_all,
+                    config.stream.global,
+                );
+                matches.extend(found.into_iter().map(|found| (Some(index), found)));
+            }
+        }
 
-                if let Some(msg) = captures.get(0) {
-                    dispatch_all(msg.as_str(), config).await?;
+        if !matches.is_empty() {
+            heartbeat_end = heartbeat.map(|heartbeat| tokio::time::Instant::now() + heartbeat);
+        }
+        for (target, (message, mut vars)) in matches {
+            if config.stream.dedupe {
+                let key = match &config.stream.dedupe_key {
+                    Some(key) => template::render(key, &message, &vars),
+                    None => message.clone(),
+                };
+                let now = tokio::time::Instant::now();
+                if !first_sighting(&mut seen, (target, key), now, dedupe_ttl) {
+                    continue;
                 }
             }
-            None => dispatch_all(&value, config).await?,
+
+            vars.insert("prev_line".into(), prev_line.clone());
+            vars.insert("prev_lines".into(), prev_lines.clone());
+            vars.insert("config_path".into(), config_path.clone());
+            let message = match (&config.stream.template, config.stream.json_line) {
+                (_, true) => value.clone(),
+                (Some(template), false) => template::render(template, &message, &vars),
+                (None, false) => message,
+            };
+            let message = match config.stream.json_line {
+                true => message,
+                false => config.stream.trim.apply(&message).to_string(),
+            };
+
+            if !holding {
+                dispatch_target(target, &message, &vars, &mut cap, priority, config, status)
+                    .await?;
+                continue;
+            }
+            pending.entry(target).or_default().push((message, vars));
+            if config.stream.buffer_all || digest.is_some() {
+                continue;
+            }
+            pending_count += 1;
+            if pending_count >= limit {
+                flush(&mut pending, &mut cap, priority, config, status).await?;
+                pending_count = 0;
+                (window_end, idle_end) = (None, None);
+                continue;
+            }
+            let now = tokio::time::Instant::now();
+            if let Some(window) = window {
+                window_end.get_or_insert(now + window);
+            }
+            if let Some(idle) = idle {
+                idle_end = Some(now + idle);
+            }
         }
     }
 
-    Ok(())
+    flush(&mut pending, &mut cap, priority, config, status).await?;
+    let summary = cap.report_suppressed(priority, config, status).await;
+    on_error(config.stream.on_error, summary)
 }
 
-/// Send a message to the configured destination.
-async fn dispatch(message: &str, destination: &Destination) -> Result<()> {
-    match destination {
-        Destination::Webhook { url, format } => dispatch_webhook(message, url, format).await,
-        Destination::Desktop {
-            summary,
-            persistent,
-        } => dispatch_desktop(message, summary, *persistent),
+/// Record that `key` was seen at `now`, returning whether it hadn't been seen
+/// within the last `ttl` (or at all, without one).
+///
+/// Keys older than `ttl` are dropped whenever a new one is added, so `seen`
+/// doesn't grow without bound while following a long running log.
+fn first_sighting<K: Eq + std::hash::Hash>(
+    seen: &mut HashMap<K, tokio::time::Instant>,
+    key: K,
+    now: tokio::time::Instant,
+    ttl: Option<Duration>,
+) -> bool {
+    let expired =
+        |sent: &tokio::time::Instant| ttl.is_some_and(|ttl| now.duration_since(*sent) >= ttl);
+    if seen.get(&key).is_some_and(|sent| !expired(sent)) {
+        return false;
     }
+    if ttl.is_some() {
+        seen.retain(|_, sent| !expired(sent));
+    }
+    seen.insert(key, now);
+    true
 }
 
-/// Send a message to all configured destinations.
-async fn dispatch_all(message: &str, config: &Config) -> Result<()> {
-    let tasks = config
-        .destination
+/// Whether `line` is a JSON object whose top level fields have the values in `filter`.
+///
+/// Fields which are strings are compared to the values as they are, and others
+/// as JSON, so `{"code": 500}` matches a `code` of `500`.
+fn json_line_matches(line: &str, filter: &IndexMap<String, String>) -> bool {
+    let Ok(serde_json::Value::Object(object)) = serde_json::from_str(line) else {
+        return false;
+    };
+    filter
         .iter()
-        .map(|destination| dispatch(message, destination));
-
-    futures::future::try_join_all(tasks).await?;
+        .all(|(field, expected)| match object.get(field) {
+            Some(serde_json::Value::String(value)) => value == expected,
+            Some(value) => {
+                serde_json::from_str(expected).is_ok_and(|e: serde_json::Value| e == *value)
+            }
+            None => false,
+        })
+}
 
-    Ok(())
+/// Read lines from `input` on another thread, so streaming can act on timers
+/// while it waits for input.
+fn read_lines(input: impl BufRead + Send + 'static) -> mpsc::Receiver<io::Result<String>> {
+    let (sender, receiver) = mpsc::channel(READ_AHEAD_LINES);
+    std::thread::spawn(move || {
+        for line in lossy_lines(input) {
+            if sender.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
 }
 
-/// Program's main entrypoint.
-///
-/// Either sends a message immediately to the configured
-/// destination, or start listening for input from stdin.
-pub async fn execute(args: Cli) -> Result<()> {
-    let config = Config::try_from(&args.config)?;
+/// How long following a file waits for more to be written before checking again.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
-    match (config.stream.enabled, args.message) {
-        (true, None) => stream_and_dispatch(&config).await,
-        (true, Some(_)) => Err(Error::StreamAndMessage),
-        (false, None) => Err(Error::NoMessage),
-        (false, Some(message)) => dispatch_all(&message, &config).await,
-    }
+/// Reads what's appended to a file like `tail -f`, starting from its end, and
+/// reopening it from the start when it's replaced or truncated. Named pipes are
+/// reopened each time their writer closes them.
+///
+/// Reads block until there's more to read, so the input never ends.
+struct Follow {
+    path: PathBuf,
+    file: std::fs::File,
+    id: Option<u64>,
+    position: u64,
 }
 
-/// Initialise a new config file at `path`.
-pub async fn init(path: &PathBuf, destination: &DestinationType, custom: bool) -> Result<()> {
-    if let Ok(true) = tokio::fs::try_exists(&path).await {
-        return Err(Error::ConfigConflict {
-            path: path.to_owned(),
-        });
+impl Follow {
+    fn open(path: &Path) -> Result<Self> {
+        let mut follow = Self {
+            path: path.to_path_buf(),
+            file: Self::open_file(path)?,
+            id: None,
+            position: 0,
+        };
+        let metadata = follow.file.metadata().map_err(|source| Error::FollowFile {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        follow.id = file_id(&metadata);
+        if metadata.is_file() {
+            follow.position = io::Seek::seek(&mut follow.file, io::SeekFrom::End(0))?;
+        }
+        Ok(follow)
     }
 
-    let config = match destination {
-        DestinationType::Desktop => Config::default_desktop(),
-        DestinationType::Webhook if custom => Config::default_custom_webhook(),
-        DestinationType::Webhook => Config::default_webhook(),
-    };
+    fn open_file(path: &Path) -> Result<std::fs::File> {
+        std::fs::File::open(path).map_err(|source| Error::FollowFile {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
 
-    let data = serde_yaml::to_string(&config)?;
-    Ok(tokio::fs::write(&path, &data).await?)
+    /// Whether the file at `path` is no longer the one being read, or is shorter
+    /// than what's been read of it.
+    fn replaced(&self) -> bool {
+        std::fs::metadata(&self.path)
+            .is_ok_and(|metadata| file_id(&metadata) != self.id || metadata.len() < self.position)
+    }
 }
 
-/// Handle destination commands.
-pub async fn destination(config: &PathBuf, command: &DestinationCommand) -> Result<()> {
-    match command {
-        DestinationCommand::List => list_destinations().await,
-        DestinationCommand::Add {
-            destination,
-            custom,
-        } => add_default_destination(config, &destination, *custom).await,
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                self.position += read as u64;
+                return Ok(read);
+            }
+            std::thread::sleep(FOLLOW_POLL_INTERVAL);
+            if self.replaced() {
+                self.file = Self::open_file(&self.path).map_err(io::Error::other)?;
+                self.id = self
+                    .file
+                    .metadata()
+                    .ok()
+                    .and_then(|metadata| file_id(&metadata));
+                self.position = 0;
+            }
+        }
     }
 }
 
-/// Print available destinations.
-async fn list_destinations() -> Result<()> {
-    println!("desktop");
-    println!("webhook");
-    Ok(())
+/// What tells files apart when one replaces another at the same path, where known.
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<u64> {
+    Some(std::os::unix::fs::MetadataExt::ino(metadata))
 }
 
-/// Add a default destination to existing config.
-async fn add_default_destination(
-    config_path: &PathBuf,
-    destination: &DestinationType,
-    custom: bool,
-) -> Result<()> {
-    let file = fs::read_to_string(&config_path).await?;
-    let config: Config = serde_yaml::from_str(file.as_str())?;
+#[cfg(not(unix))]
+fn file_id(_: &std::fs::Metadata) -> Option<u64> {
+    None
+}
 
-    let dest = match destination {
-        DestinationType::Webhook if custom => Destination::default_custom_webhook(),
-        DestinationType::Webhook => Destination::default_webhook(),
-        DestinationType::Desktop => Destination::default_desktop(),
-    };
+/// Messages waiting to be sent together, by the destination they're for.
+type Pending = IndexMap<Option<usize>, Vec<(String, Vars)>>;
 
-    let mut destination = vec![dest];
-    destination.extend(config.destination);
+/// Send each destination's pending messages as one, each after
+/// `stream.batch_line_prefix` and joined by `stream.batch_separator`.
+///
+/// The captures of the last message are used for the combined one, along with
+/// `$(count)`, the number of messages in it.
+async fn flush(
+    pending: &mut Pending,
+    cap: &mut RateCap,
+    priority: Option<Priority>,
+    config: &Config,
+    status: &Status,
+) -> Result<()> {
+    for (target, messages) in pending.drain(..) {
+        let mut vars = messages
+            .last()
+            .map(|(_, vars)| vars.clone())
+            .unwrap_or_default();
+        vars.insert("count".into(), messages.len().to_string());
+        let prefix = config
+            .stream
+            .batch_line_prefix
+            .as_deref()
+            .unwrap_or_default();
+        let message = messages
+            .into_iter()
+            .map(|(message, _)| format!("{prefix}{message}"))
+            .collect::<Vec<_>>()
+            .join(config.stream.batch_separator.as_deref().unwrap_or("\n"));
+        dispatch_target(target, &message, &vars, cap, priority, config, status).await?;
+    }
+    Ok(())
+}
 
-    let new_config = Config {
-        destination,
-        ..config
-    };
+/// Send a streamed message to the destination at index `target`, or to every
+/// destination without a `matching` of its own if `None`.
+///
+/// Messages over the `max_per_minute` cap are dropped, and once there's room
+/// again, a summary of how many were is sent first. Failures are only returned
+/// if `stream.on_error` is `stop`.
+async fn dispatch_target(
+    target: Option<usize>,
+    message: &str,
+    vars: &Vars,
+    cap: &mut RateCap,
+    priority: Option<Priority>,
+    config: &Config,
+    status: &Status,
+) -> Result<()> {
+    if !cap.admit() {
+        return Ok(());
+    }
+    let summary = cap.report_suppressed(priority, config, status).await;
+    on_error(config.stream.on_error, summary)?;
 
-    let content = serde_yaml::to_string(&new_config)?;
-    Ok(fs::write(&config_path, content).await?)
+    let result = dispatch_some(
+        message,
+        vars,
+        priority,
+        config,
+        status,
+        config.stream.json_line,
+        |index| match target {
+            Some(target) => index == target,
+            None => config.destination[index].matching.is_none(),
+        },
+    )
+    .await;
+    status.record_dispatch(&result);
+    on_error(config.stream.on_error, result)
 }
 
-#[cfg(test)]
-mod test {
-    use super::{
-        add_default_destination, dispatch_webhook, init, DestinationType, Error, PathBuf, Result,
-        WebhookFormat,
+/// Failures to send a streamed message, if `stream.on_error` stops streaming on them.
+fn on_error(on_error: OnError, result: Result<()>) -> Result<()> {
+    match (on_error, result) {
+        (OnError::ContinueLog, Err(e)) => {
+            eprintln!("WARNING: Failed to send a streamed message: {e}");
+            Ok(())
+        }
+        (OnError::Continue, Err(_)) => Ok(()),
+        (_, result) => result,
+    }
+}
+
+/// Notifications streamed within the last minute, to cap them at `max_per_minute`.
+struct RateCap {
+    limit: Option<usize>,
+    sent: VecDeque<tokio::time::Instant>,
+    suppressed: usize,
+}
+
+impl RateCap {
+    fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            sent: VecDeque::new(),
+            suppressed: 0,
+        }
+    }
+
+    /// Whether a notification may be sent now, counting it if so and
+    /// counting it as suppressed otherwise.
+    fn admit(&mut self) -> bool {
+        let Some(limit) = self.limit else {
+            return true;
+        };
+        let now = tokio::time::Instant::now();
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= Duration::from_secs(60))
+        {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= limit {
+            self.suppressed += 1;
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+
+    /// Tell every destination how many notifications were suppressed since
+    /// the last report, if any were.
+    async fn report_suppressed(
+        &mut self,
+        priority: Option<Priority>,
+        config: &Config,
+        status: &Status,
+    ) -> Result<()> {
+        if self.suppressed == 0 {
+            return Ok(());
+        }
+        let message = format!(
+            "Suppressed {} notification(s) over the limit of {} per minute",
+            std::mem::take(&mut self.suppressed),
+            self.limit.unwrap_or_default()
+        );
+        dispatch_some(
+            &message,
+            &Vars::new(),
+            priority,
+            config,
+            status,
+            false,
+            |_| true,
+        )
+        .await
+    }
+}
+
+/// Split `input` into lines like [`BufRead::lines`], but replace invalid
+/// UTF-8 rather than failing on it.
+fn lossy_lines(input: impl BufRead) -> impl Iterator<Item = io::Result<String>> {
+    input.split(b'\n').map(|line| {
+        let line = line?;
+        let line = line.strip_suffix(b"\r").unwrap_or(&line);
+        Ok(String::from_utf8_lossy(line).into_owned())
+    })
+}
+
+/// Read `input` to the end as a single message of at most `max_bytes`.
+///
+/// Invalid UTF-8 is replaced rather than rejected, and a trailing newline is dropped.
+fn read_message(input: impl Read, max_bytes: u64) -> Result<String> {
+    let mut buf = vec![];
+    input.take(max_bytes).read_to_end(&mut buf)?;
+
+    let message = String::from_utf8_lossy(&buf);
+    Ok(message.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Read the whole of the file at `path` as a message, or at most `max_stdin_bytes`
+/// of stdin if `path` is `-`.
+fn read_message_file(path: &Path, max_stdin_bytes: u64) -> Result<String> {
+    if path == Path::new("-") {
+        return read_message(io::stdin().lock(), max_stdin_bytes);
+    }
+    let file = std::fs::File::open(path).map_err(|source| Error::MessageFile {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    read_message(file, u64::MAX)
+}
+
+/// Compile each of the `matching` patterns.
+fn compile(matching: &Matching) -> Result<Vec<Regex>> {
+    matching
+        .patterns()
+        .iter()
+        .map(|pattern| Ok(Regex::new(pattern)?))
+        .collect()
+}
+
+/// Match `line` against the compiled `patterns`.
+///
+/// Returns each message to send with its named captures, which is empty if
+/// the line should be skipped. With `match_all` every pattern must match and
+/// the whole line is sent. Otherwise the first pattern to match supplies the
+/// message, or with `global` one message for each of its matches in the line.
+fn match_line(
+    line: &str,
+    patterns: &[Regex],
+    match_all: bool,
+    global: bool,
+) -> Vec<(String, Vars)> {
+    let mut vars = Vars::new();
+
+    for re in patterns {
+        let mut captures = re.captures_iter(line);
+
+        if match_all {
+            match captures.next() {
+                Some(captures) => vars.extend(named_captures(re, &captures)),
+                None => return vec![],
+            }
+            continue;
+        }
+
+        let limit = if global { usize::MAX } else { 1 };
+        let matches: Vec<_> = captures
+            .take(limit)
+            .map(|captures| (captures[0].to_string(), named_captures(re, &captures)))
+            .collect();
+        if !matches.is_empty() {
+            return matches;
+        }
+    }
+
+    match match_all {
+        true => vec![(line.to_string(), vars)],
+        false => vec![],
+    }
+}
+
+/// Collect the named groups of a match, with those which took no part in it empty.
+fn named_captures(re: &Regex, captures: &regex::Captures) -> Vars {
+    re.capture_names()
+        .flatten()
+        .map(|name| {
+            let value = captures.name(name).map_or("", |value| value.as_str());
+            (name.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Pick the desktop summary, preferring the `summary_capture` group if it matched.
+fn desktop_summary<'a>(summary: &'a str, capture: Option<&str>, vars: &'a Vars) -> &'a str {
+    capture
+        .and_then(|name| vars.get(name))
+        .filter(|value| !value.is_empty())
+        .map_or(summary, String::as_str)
+}
+
+/// Send a message to a destination, or its fallbacks in turn if that fails.
+///
+/// At most [`MAX_FALLBACKS`] fallbacks are tried, and the last failure is returned.
+async fn dispatch(
+    message: &str,
+    vars: &Vars,
+    mut destination: &Destination,
+    options: &RequestOptions,
+) -> Result<()> {
+    let mut fallbacks = 0;
+
+    loop {
+        match dispatch_fitted(message, vars, destination, options).await {
+            Err(e) if fallbacks < MAX_FALLBACKS && destination.fallback.is_some() => {
+                eprintln!(
+                    "WARNING: Sending to {} failed, trying its fallback: {e}",
+                    destination.kind()
+                );
+                destination = destination.fallback.as_deref().expect("Checked above");
+                fallbacks += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Send a message to the destination, truncated or split to fit its `max_length`.
+///
+/// With `overflow: paste`, a message which doesn't fit is uploaded first, and
+/// sent truncated with the link to it.
+async fn dispatch_fitted(
+    message: &str,
+    vars: &Vars,
+    destination: &Destination,
+    options: &RequestOptions,
+) -> Result<()> {
+    if let Overflow::Paste { url } = &destination.overflow {
+        if destination.overflows(message) {
+            let link = upload_paste(url, message, options).await?;
+            let message = destination.truncate_with_link(message, &link);
+            return dispatch_to(&message, vars, destination, options).await;
+        }
+    }
+    for part in destination.fit(message) {
+        dispatch_to(&part, vars, destination, options).await?;
+    }
+    Ok(())
+}
+
+/// Upload `message` as plain text to the paste service at `url`, returning the
+/// link it replies with.
+async fn upload_paste(url: &str, message: &str, options: &RequestOptions) -> Result<String> {
+    let request = http_client(options.timeout, options)?
+        .post(template::expand_env(url, |name| options.env.var(name))?)
+        .header(reqwest::header::CONTENT_TYPE, "text/plain")
+        .body(message.to_string());
+    if options.dry_run {
+        print_dry_run(request, options)?;
+        return Ok("<paste link>".into());
+    }
+    Ok(send(request, options)
+        .await?
+        .text()
+        .await?
+        .trim()
+        .to_string())
+}
+
+/// Send a message to the configured destination.
+///
+/// Requests to network destinations are sent according to `options`.
+async fn dispatch_to(
+    message: &str,
+    vars: &Vars,
+    destination: &Destination,
+    options: &RequestOptions,
+) -> Result<()> {
+    let options = &RequestOptions {
+        follow_redirects: destination.follow_redirects,
+        mask_message: options.mask_message || destination.sensitive,
+        charset: destination.charset.clone(),
+        ..options.clone()
     };
-    use crate::config::{Config, CustomWebhookFormat, Http, HttpMethod, StandardWebhookFormat};
-    use indexmap::IndexMap;
+    match &destination.name {
+        Some(name) => tracing::info!("Sending to {} `{name}`", destination.kind()),
+        None => tracing::info!("Sending to {}", destination.kind()),
+    }
+    tracing::debug!("Message: {}", masked(message, options.mask_message));
+    if options.dry_run {
+        if let Some(target) = dry_run_target(destination, vars) {
+            let message = masked(message, options.mask_message);
+            log_dry_run(format!("Would send to {target}\n{message}"), options);
+            return Ok(());
+        }
+    }
+
+    match &destination.kind {
+        DestinationKind::Webhook { url, format, title } => {
+            let url = template::render_url(url, vars);
+            let title = title
+                .as_deref()
+                .map(|title| template::render(title, message, vars));
+            dispatch_webhook(message, vars, &url, format, title.as_deref(), options).await
+        }
+        DestinationKind::Desktop {
+            summary,
+            persistent,
+            summary_capture,
+            icon,
+            backend,
+            replaces_id,
+            hints,
+        } => {
+            let summary = desktop_summary(summary, summary_capture.as_deref(), vars);
+            let failed = vars.get("exit_code").is_some_and(|code| code != "0");
+            #[cfg(not(feature = "dbus"))]
+            let _ = (replaces_id, hints);
+            match backend {
+                DesktopBackend::NotifyRust => dispatch_desktop(
+                    message,
+                    summary,
+                    *persistent,
+                    icon.as_deref(),
+                    failed,
+                    progress_hint(vars),
+                ),
+                #[cfg(feature = "dbus")]
+                DesktopBackend::Dbus => {
+                    let notification = crate::dbus::Notification {
+                        summary,
+                        body: message,
+                        icon: icon.as_deref(),
+                        persistent: *persistent,
+                        failed,
+                        progress: progress_percent(vars),
+                        replaces_id: *replaces_id,
+                        hints,
+                    };
+                    crate::dbus::dispatch(&notification).await.map(|_| ())
+                }
+            }
+        }
+        DestinationKind::Telegram { token, chat_id } => {
+            dispatch_telegram(message, token, chat_id, options).await
+        }
+        DestinationKind::Email(email) => dispatch_email(message, email, options).await,
+        DestinationKind::PagerDuty {
+            routing_key,
+            severity,
+        } => dispatch_pagerduty(message, routing_key, *severity, options).await,
+        DestinationKind::File {
+            path,
+            append,
+            format,
+        } => dispatch_file(message, vars, path, *append, format.as_deref()).await,
+        DestinationKind::Exec {
+            command,
+            args,
+            timeout_secs,
+        } => {
+            let timeout = timeout_secs.map(Duration::from_secs);
+            dispatch_exec(message, vars, command, args, timeout).await
+        }
+        #[cfg(feature = "grpc")]
+        DestinationKind::Grpc {
+            endpoint,
+            service,
+            method,
+        } => crate::grpc::dispatch(message, endpoint, service, method).await,
+        #[cfg(feature = "kafka")]
+        DestinationKind::Kafka {
+            brokers,
+            topic,
+            key,
+        } => {
+            let key = key.as_ref().map(|key| template::render(key, message, vars));
+            crate::kafka::dispatch(message, brokers, topic, key.as_deref(), options.timeout).await
+        }
+        DestinationKind::Auto { desktop, headless } => {
+            let target = auto_target(desktop, headless, |name| options.env.var(name));
+            Box::pin(dispatch(message, vars, target, options)).await
+        }
+    }
+}
+
+/// The destination an `auto` destination sends to, judging by the environment `var`.
+fn auto_target<'a>(
+    desktop: &'a Destination,
+    headless: &'a Destination,
+    var: impl Fn(&str) -> Option<OsString>,
+) -> &'a Destination {
+    match has_display(var) {
+        true => desktop,
+        false => headless,
+    }
+}
+
+/// Whether desktop notifications can be shown, judging by the environment `var`.
+///
+/// There's taken to be no display over SSH, nor on Linux and the BSDs without
+/// an X11 or Wayland display.
+fn has_display(var: impl Fn(&str) -> Option<OsString>) -> bool {
+    let set = |name| var(name).is_some_and(|value| !value.is_empty());
+    if set("SSH_CONNECTION") || set("SSH_TTY") {
+        return false;
+    }
+    !cfg!(all(unix, not(target_os = "macos"))) || set("DISPLAY") || set("WAYLAND_DISPLAY")
+}
+
+/// Describe where a message to a destination which isn't sent over HTTP would go.
+///
+/// Webhook, Telegram and PagerDuty destinations describe their requests themselves,
+/// and `auto` destinations leave it to the one they pick.
+fn dry_run_target(destination: &Destination, vars: &Vars) -> Option<String> {
+    match &destination.kind {
+        DestinationKind::Webhook { .. }
+        | DestinationKind::Telegram { .. }
+        | DestinationKind::PagerDuty { .. }
+        | DestinationKind::Auto { .. } => None,
+        DestinationKind::Desktop {
+            summary,
+            summary_capture,
+            ..
+        } => {
+            let summary = desktop_summary(summary, summary_capture.as_deref(), vars);
+            Some(format!("desktop, titled {summary:?}"))
+        }
+        DestinationKind::Email(email) => Some(format!(
+            "email to {} via {}, titled {:?}",
+            email.to, email.smtp_host, email.subject
+        )),
+        #[cfg(feature = "grpc")]
+        DestinationKind::Grpc {
+            endpoint,
+            service,
+            method,
+        } => Some(format!("gRPC {endpoint}/{service}/{method}")),
+        DestinationKind::Exec { command, args, .. } => {
+            Some(format!("command `{command}` {args:?}"))
+        }
+        DestinationKind::File { path, append, .. } => Some(format!(
+            "file {}{}",
+            path.display(),
+            if *append { ", appended" } else { "" }
+        )),
+        #[cfg(feature = "kafka")]
+        DestinationKind::Kafka { brokers, topic, .. } => {
+            Some(format!("Kafka topic {topic} on {brokers}"))
+        }
+    }
+}
+
+/// Send a message to all configured destinations.
+///
+/// Nothing is sent if the message is throttled, though a message which every destination
+/// failed to send isn't counted against the throttle. Destinations are skipped if the
+/// message is outside their size bounds or their priority falls within configured quiet
+/// hours. How long each send takes is recorded in `status`.
+///
+/// Every destination is tried even if others fail, unless `fail_fast` is set.
+/// If several fail, they're reported together as [`Error::DestinationsFailed`].
+pub(crate) async fn dispatch_all(
+    message: &str,
+    vars: &Vars,
+    priority: Option<Priority>,
+    config: &Config,
+    status: &Status,
+) -> Result<()> {
+    dispatch_some(message, vars, priority, config, status, false, |_| true).await
+}
+
+/// Like [`dispatch_all`], but only to the destinations whose index `include` accepts.
+///
+/// With `raw_json`, the message is sent as the body of webhook requests as it is.
+async fn dispatch_some(
+    message: &str,
+    vars: &Vars,
+    priority: Option<Priority>,
+    config: &Config,
+    status: &Status,
+    raw_json: bool,
+    include: impl Fn(usize) -> bool,
+) -> Result<()> {
+    let claim = match config.throttle.as_ref().filter(|_| !config.dry_run) {
+        Some(throttle) => match throttle::claim(throttle, message)? {
+            Some(claim) => Some(claim),
+            None => return Ok(()),
+        },
+        None => None,
+    };
+    let sent = AtomicBool::new(false);
+    let result = send_some(
+        message, vars, priority, config, status, raw_json, include, &sent,
+    )
+    .await;
+    if let Some(claim) = claim.filter(|_| result.is_err() && !sent.load(Ordering::Relaxed)) {
+        claim.release()?;
+    }
+    result
+}
+
+/// The sends of [`dispatch_some`], setting `sent` once any destination succeeds.
+#[allow(clippy::too_many_arguments)]
+async fn send_some(
+    message: &str,
+    vars: &Vars,
+    priority: Option<Priority>,
+    config: &Config,
+    status: &Status,
+    raw_json: bool,
+    include: impl Fn(usize) -> bool,
+    sent: &AtomicBool,
+) -> Result<()> {
+    let options = &RequestOptions {
+        raw_json,
+        ..config.request_options()
+    };
+    let now = chrono::Utc::now();
+    let tasks = config
+        .destination
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| include(*index))
+        .filter(|(_, destination)| destination.accepts(message))
+        .filter(|(_, destination)| {
+            config.quiet_hours.as_ref().is_none_or(|quiet_hours| {
+                !quiet_hours.suppresses(destination.priority(priority), now)
+            })
+        })
+        .map(|(index, destination)| async move {
+            let start = Instant::now();
+            let result = dispatch(message, vars, destination, options).await;
+            status.record_latency(index, start.elapsed());
+            status.record_outcome(index, destination, &result);
+            if result.is_ok() {
+                sent.store(true, Ordering::Relaxed);
+            }
+            result.map_err(|e| (index, e))
+        });
+    let sends = futures::stream::iter(tasks).buffer_unordered(config.concurrency());
+
+    if config.fail_fast.unwrap_or_default() {
+        sends.map_err(|(_, e)| e).try_collect::<Vec<_>>().await?;
+        return Ok(());
+    }
+
+    let mut failures: Vec<_> = sends
+        .filter_map(|result| async { result.err() })
+        .collect()
+        .await;
+    failures.sort_by_key(|(index, _)| *index);
+    match failures.len() {
+        0 => Ok(()),
+        1 => Err(failures.remove(0).1),
+        _ => Err(Error::DestinationsFailed {
+            failures: failures
+                .into_iter()
+                .map(|(index, e)| (config.destination[index].label(index), e))
+                .collect(),
+        }),
+    }
+}
+
+/// Install a panic hook which makes a single best-effort attempt to tell
+/// `destination` that noti crashed, before deferring to the previous hook.
+fn install_crash_hook(destination: Destination, options: RequestOptions) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        notify_crash(&format!("noti crashed: {info}"), &destination, &options);
+        previous(info);
+    }));
+}
+
+/// Synchronously dispatch a crash message, ignoring any failure.
+///
+/// Runs on a fresh thread and runtime, as the panicking thread may
+/// already be driving one. Only one attempt is made, without retries, rate
+/// limit waits or fallbacks, and it's abandoned after [`CRASH_NOTIFY_TIMEOUT`].
+fn notify_crash(message: &str, destination: &Destination, options: &RequestOptions) {
+    let options = &RequestOptions {
+        timeout: Some(CRASH_NOTIFY_TIMEOUT),
+        retry: None,
+        max_rate_limit_wait: Duration::ZERO,
+        ..options.clone()
+    };
+    std::thread::scope(|scope| {
+        let _ = scope
+            .spawn(|| -> Result<()> {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?
+                    .block_on(async {
+                        let vars = Vars::new();
+                        let send = dispatch_to(message, &vars, destination, options);
+                        tokio::time::timeout(CRASH_NOTIFY_TIMEOUT, send)
+                            .await
+                            .map_err(|_| Error::Timeout)?
+                    })
+            })
+            .join();
+    });
+}
+
+/// Call `notify` with a summary and body if `error` means the config couldn't be parsed.
+///
+/// Returns whether `notify` was called.
+fn report_invalid_config(error: &Error, notify: impl FnOnce(&str, &str)) -> bool {
+    match error {
+        Error::InvalidConfig(_) | Error::UnknownConfigKeys { .. } => {
+            notify("noti config invalid", &error.to_string());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Make a best-effort attempt to show a desktop notification if `error`
+/// means the config couldn't be parsed.
+pub fn notify_invalid_config(error: &Error) {
+    report_invalid_config(error, |summary, body| {
+        let _ = dispatch_desktop(body, summary, false, None, true, None);
+    });
+}
+
+/// Warn or fail, according to `on_empty`, if `config` has no destinations.
+fn check_destinations(config: &Config, on_empty: OnEmpty) -> Result<()> {
+    if !config.destination.is_empty() {
+        return Ok(());
+    }
+
+    match on_empty {
+        OnEmpty::Warn => {
+            eprintln!("WARNING: {}", Error::NoDestinations);
+            Ok(())
+        }
+        OnEmpty::Error => Err(Error::NoDestinations),
+    }
+}
+
+/// Program's main entrypoint.
+///
+/// Either sends a message immediately to the configured
+/// destination, or start listening for input from stdin.
+pub async fn execute(args: Cli, status: Arc<Status>) -> Result<()> {
+    let mut config = Config::load_all(&args.config, args.strict)?;
+    config.select_destinations(&args.to)?;
+    check_destinations(&config, args.on_empty)?;
+    if args.buffer_all {
+        config.stream.enabled = true;
+        config.stream.buffer_all = true;
+    }
+    if args.input_json_line {
+        config.stream.enabled = true;
+        config.stream.json_line = true;
+    }
+    if let Some(path) = &args.follow {
+        config.stream.enabled = true;
+        config.stream.source = Some(path.clone());
+    }
+    config.dry_run = args.dry_run;
+    config.mask_message = args.mask_message;
+
+    if let Some(destination) = config.crash_destination.take() {
+        install_crash_hook(destination, config.request_options());
+    }
+
+    if let Some(addr) = args.serve_status {
+        status::serve(addr, status.clone()).await?;
+    }
+
+    let priority = args.priority;
+
+    let mut vars = Vars::from([("config_path".into(), config.config_path())]);
+    if let Some(exit_code) = args.exit_code {
+        vars.insert("exit_code".into(), exit_code.to_string());
+    }
+    if let Some(severity) = args.severity {
+        vars.insert("severity".into(), severity.as_str().into());
+    }
+
+    let message = message_to_send(&args, &config, &vars, io::stdin().lock())?;
+
+    let result = match (config.stream.enabled, message) {
+        (true, None) => match &config.stream.source {
+            Some(path) => {
+                let input = io::BufReader::new(Follow::open(path)?);
+                stream_and_dispatch(input, &config, priority, &status).await
+            }
+            None => {
+                let input = io::BufReader::new(io::stdin());
+                stream_and_dispatch(input, &config, priority, &status).await
+            }
+        },
+        (true, Some(_)) => Err(Error::StreamAndMessage),
+        (false, None) => Err(Error::NoMessage),
+        (false, Some(message)) => {
+            let result = dispatch_all(&message, &vars, priority, &config, &status).await;
+            status.record_dispatch(&result);
+            result
+        }
+    };
+
+    if args.summary {
+        eprint!("{}", status.latency_summary(&config.destination));
+    }
+
+    result
+}
+
+/// The message given to send, from `--message-file`, from `stdin` with `--stdin`,
+/// or as arguments, with `vars` such as `$(exit_code)` substituted into it.
+fn message_to_send(
+    args: &Cli,
+    config: &Config,
+    vars: &Vars,
+    stdin: impl Read,
+) -> Result<Option<String>> {
+    let given = args.message();
+    let message = match (&args.message_file, args.stdin) {
+        (Some(_), _) if config.stream.enabled => return Err(Error::StreamAndMessage),
+        (Some(path), _) => {
+            if given.is_some() {
+                eprintln!(
+                    "WARNING: Sending the message from --message-file instead of the one given"
+                );
+            }
+            Some(read_message_file(path, args.max_stdin_bytes)?)
+        }
+        (None, true) if given.is_some() => return Err(Error::StdinAndMessage),
+        (None, true) if config.stream.enabled => return Err(Error::StreamAndMessage),
+        (None, true) => Some(read_message(stdin, args.max_stdin_bytes)?),
+        (None, false) => given,
+    };
+    Ok(message.map(|message| template::substitute(&message, |name| vars.get(name).cloned())))
+}
+
+/// Initialise a new config file at `path`.
+///
+/// When `test` is set, a test notification is sent once the file is written.
+/// The file is kept even if the test fails.
+pub async fn init(
+    path: &PathBuf,
+    destination: &DestinationType,
+    custom: bool,
+    test: bool,
+) -> Result<()> {
+    if let Ok(true) = tokio::fs::try_exists(&path).await {
+        return Err(Error::ConfigConflict {
+            path: path.to_owned(),
+        });
+    }
+
+    let config = match destination {
+        DestinationType::Desktop => Config::default_desktop(),
+        DestinationType::Webhook if custom => Config::default_custom_webhook(),
+        DestinationType::Webhook => Config::default_webhook(),
+        DestinationType::Slack => Config::default_slack_webhook(),
+        DestinationType::Mattermost => Config::default_mattermost_webhook(),
+        DestinationType::Telegram => Config::default_telegram(),
+        DestinationType::Email => Config::default_email(),
+        DestinationType::PagerDuty => Config::default_pagerduty(),
+    };
+
+    let data = config.to_string_as(ConfigFormat::of(path))?;
+    tokio::fs::write(&path, &data).await?;
+
+    match test {
+        true => test_destinations(&config).await,
+        false => Ok(()),
+    }
+}
+
+/// Format `elapsed` to the second, like `3m12s`.
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m{s}s"),
+        (h, m, s) => format!("{h}h{m}m{s}s"),
+    }
+}
+
+/// Run `command` with inherited stdio, then notify every destination in the
+/// configs at `paths`, or only those named in `to`, of its exit status and how
+/// long it took.
+///
+/// The exit code is available to destinations as `$(exit_code)`, so failures
+/// are shown with critical urgency on the desktop.
+pub async fn run(
+    paths: &[PathBuf],
+    to: &[String],
+    command: &[String],
+    priority: Option<Priority>,
+    severity: Option<Severity>,
+    status: &Status,
+) -> Result<()> {
+    let mut config = Config::load_all(paths, false)?;
+    config.select_destinations(to)?;
+    if let Some(destination) = config.crash_destination.take() {
+        install_crash_hook(destination, config.request_options());
+    }
+    let (program, args) = command.split_first().expect("clap requires a command");
+
+    let start = Instant::now();
+    let exit = tokio::process::Command::new(program)
+        .args(args)
+        .status()
+        .await
+        .map_err(Error::Io)?;
+    let elapsed = format_elapsed(start.elapsed());
+
+    let mut vars = Vars::from([("config_path".into(), config.config_path())]);
+    let outcome = match exit.code() {
+        Some(code) => {
+            vars.insert("exit_code".into(), code.to_string());
+            format!("exit {code}")
+        }
+        None => "killed by a signal".into(),
+    };
+    if let Some(severity) = severity {
+        vars.insert("severity".into(), severity.as_str().into());
+    }
+    let message = format!("{} finished in {elapsed} ({outcome})", command.join(" "));
+
+    dispatch_all(&message, &vars, priority, &config, status).await
+}
+
+/// Send a test notification to every destination in the configs at `paths`.
+pub async fn test(paths: &[PathBuf]) -> Result<()> {
+    let config = Config::load_all(paths, false)?;
+    test_destinations(&config).await
+}
+
+/// Run each line of `sample` through the stream pipeline of the configs at `paths`,
+/// printing what it would send without sending anything.
+pub async fn preview(paths: &[PathBuf], sample: &Path) -> Result<()> {
+    let config = Config::load_all(paths, false)?;
+    let file = std::fs::File::open(sample).map_err(|source| Error::SampleFile {
+        path: sample.to_path_buf(),
+        source,
+    })?;
+    preview_lines(config, io::BufReader::new(file), &mut io::stdout()).await
+}
+
+/// Write each line of `input` to `out`, followed by what streaming it to the
+/// destinations in `config` would send.
+///
+/// The whole of `input` is streamed at once, as it would be for real, so
+/// dedupe, rate limits and `$(prev_line)` apply across lines. Messages held to
+/// send together are shown after the line which was read last when they'd be
+/// sent, which for anything held until the input ends is the last line.
+async fn preview_lines(
+    mut config: Config,
+    mut input: impl BufRead,
+    out: &mut impl io::Write,
+) -> Result<()> {
+    let log = DryRunLog::default();
+    config.dry_run = true;
+    config.dry_run_log = Some(log.clone());
+    config.stream.redirect = None;
+
+    let mut sample = vec![];
+    input.read_to_end(&mut sample)?;
+    let lines = lossy_lines(sample.as_slice()).collect::<io::Result<Vec<_>>>()?;
+    stream_and_dispatch(io::Cursor::new(sample), &config, None, &Status::default()).await?;
+
+    let sent = std::mem::take(&mut log.lock().unwrap().sent);
+    for (index, line) in lines.iter().enumerate() {
+        writeln!(out, "line {}: {line}", index + 1)?;
+        let mut descriptions = sent
+            .iter()
+            .filter(|(number, _)| *number == index + 1)
+            .peekable();
+        if descriptions.peek().is_none() {
+            writeln!(out, "  nothing sent")?;
+        }
+        for (_, description) in descriptions {
+            for description_line in description.lines() {
+                writeln!(out, "  {description_line}")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check the configs at `paths` without sending anything, reporting each problem.
+pub fn validate(paths: &[PathBuf], strict: bool) -> Result<()> {
+    let config = Config::load_all(paths, strict)?;
+    validate_config(&config)
+}
+
+/// Check for problems which would otherwise only be found when sending, such as
+/// invalid URLs, headers or patterns, printing the outcome for each part.
+fn validate_config(config: &Config) -> Result<()> {
+    let mut failed = 0;
+    let mut report = |name: String, result: Result<()>| match result {
+        Ok(()) => println!("ok: {name}"),
+        Err(e) => {
+            failed += 1;
+            println!("failed: {name}: {e}");
+        }
+    };
+
+    if let Some(matching) = &config.stream.matching {
+        report("stream.matching".into(), compile(matching).map(drop));
+    }
+    for (index, destination) in config.destination.iter().enumerate() {
+        let name = format!("{} #{}", destination.kind(), index + 1);
+        report(name, validate_destination(destination));
+    }
+    if let Some(destination) = &config.crash_destination {
+        let name = format!("crash_destination ({})", destination.kind());
+        report(name, validate_destination(destination));
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::ValidationFailed { failed }),
+    }
+}
+
+/// Check a destination and each of its fallbacks.
+fn validate_destination(mut destination: &Destination) -> Result<()> {
+    loop {
+        if let Some(matching) = &destination.matching {
+            compile(matching)?;
+        }
+        match &destination.kind {
+            DestinationKind::Webhook { url, format, .. } => {
+                // Placeholders are only filled in when sending, so stand in for them.
+                let url = template::substitute(url, |_| Some("placeholder".into()));
+                let url = template::expand_env(&url, |name| std::env::var_os(name)).unwrap_or(url);
+                url::Url::parse(&url)?;
+                if let WebhookFormat::Custom(format) = format {
+                    format.http.headers.as_header_map()?;
+                }
+            }
+            DestinationKind::Email(email) => {
+                email.from.parse::<lettre::message::Mailbox>()?;
+                email.to.parse::<lettre::message::Mailbox>()?;
+            }
+            DestinationKind::Auto { desktop, headless } => {
+                validate_destination(desktop)?;
+                validate_destination(headless)?;
+            }
+            _ => (),
+        }
+
+        match &destination.fallback {
+            Some(fallback) => destination = fallback,
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Send a test notification to each destination, reporting each outcome.
+///
+/// Destinations are probed concurrently, bounded by `max_concurrency`.
+async fn test_destinations(config: &Config) -> Result<()> {
+    let vars = Vars::new();
+    let options = config.request_options();
+    let tasks = config
+        .destination
+        .iter()
+        .map(|destination| dispatch(TEST_MESSAGE, &vars, destination, &options));
+
+    let results: Vec<Result<()>> = futures::stream::iter(tasks)
+        .buffered(config.concurrency())
+        .collect()
+        .await;
+
+    let mut failed = 0;
+    for (destination, result) in config.destination.iter().zip(results) {
+        match result {
+            Ok(()) => println!("ok: {}", destination.kind()),
+            Err(e) => {
+                failed += 1;
+                println!("failed: {}: {e}", destination.kind());
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::TestFailed { failed }),
+    }
+}
+
+/// Version and build information for this binary.
+#[derive(Debug, serde::Serialize)]
+struct VersionInfo {
+    name: &'static str,
+    version: &'static str,
+    commit: &'static str,
+    features: Vec<&'static str>,
+}
+
+impl VersionInfo {
+    fn current() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("NOTI_COMMIT"),
+            features: env!("NOTI_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
+}
+
+/// Print version information, optionally as JSON.
+pub async fn version(json: bool) -> Result<()> {
+    let info = VersionInfo::current();
+
+    match json {
+        true => println!(
+            "{}",
+            serde_json::to_string(&info).expect("Serde serialize for `VersionInfo`")
+        ),
+        false => println!("{} {} ({})", info.name, info.version, info.commit),
+    }
+
+    Ok(())
+}
+
+/// Write a completion script for `shell` to `out`.
+pub fn completions(shell: clap_complete::Shell, out: &mut impl io::Write) -> Result<()> {
+    clap_complete::generate(shell, &mut Cli::command(), "noti", out);
+    Ok(())
+}
+
+/// Handle destination commands.
+pub async fn destination(config: &PathBuf, command: &DestinationCommand) -> Result<()> {
+    match command {
+        DestinationCommand::List => list_destinations().await,
+        DestinationCommand::Add {
+            destination,
+            custom,
+        } => add_default_destination(config, destination, *custom).await,
+    }
+}
+
+/// Print available destinations.
+async fn list_destinations() -> Result<()> {
+    println!("desktop");
+    println!("webhook");
+    println!("slack");
+    println!("mattermost");
+    println!("telegram");
+    println!("email");
+    println!("pagerduty");
+    Ok(())
+}
+
+/// Add a default destination to existing config.
+async fn add_default_destination(
+    config_path: &PathBuf,
+    destination: &DestinationType,
+    custom: bool,
+) -> Result<()> {
+    let format = ConfigFormat::of(config_path);
+    let file = fs::read_to_string(&config_path).await?;
+    let config = Config::parse(&file, format, false)?;
+
+    let dest = match destination {
+        DestinationType::Webhook if custom => Destination::default_custom_webhook(),
+        DestinationType::Webhook => Destination::default_webhook(),
+        DestinationType::Slack => Destination::default_slack_webhook(),
+        DestinationType::Mattermost => Destination::default_mattermost_webhook(),
+        DestinationType::Telegram => Destination::default_telegram(),
+        DestinationType::Email => Destination::default_email(),
+        DestinationType::PagerDuty => Destination::default_pagerduty(),
+        DestinationType::Desktop => Destination::default_desktop(),
+    };
+
+    let mut destination = vec![dest];
+    destination.extend(config.destination);
+
+    let new_config = Config {
+        destination,
+        ..config
+    };
+
+    let content = new_config.to_string_as(format)?;
+    Ok(fs::write(&config_path, content).await?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        add_default_destination, auto_target, check_destinations, compile, completions,
+        describe_request, desktop_summary, dispatch, dispatch_all, execute, first_sighting,
+        format_elapsed, has_display, http_client, init, install_crash_hook, json_line_matches,
+        lossy_lines, masked, match_line, message_to_send, named_captures, notify_crash,
+        preview_lines, progress_hint, read_message, redact_url, report_invalid_config,
+        response_log, run, send_pagerduty, send_telegram, stream_and_dispatch, test_destinations,
+        validate_config, DestinationType, Error, Follow, HashMap, PathBuf, Result, Vars,
+        VersionInfo,
+    };
+    use crate::{
+        cli::{Cli, OnEmpty},
+        config::{
+            Config, ConfigFormat, Destination, DestinationKind, DryRunLog, Email, Env, Matching,
+            OnError, Overflow, RequestOptions, Retry, SmtpTls, StandardWebhookFormat, Stream,
+            Throttle, Tls, Trim, WebhookFormat,
+        },
+        mock::{MockServer, MockSmtpServer, Reply, TempDir},
+        status::Status,
+    };
+    use clap::Parser;
+    use regex::Regex;
+    use std::{ffi::OsString, io::Read, sync::Arc, time::Duration};
+
+    #[cfg(feature = "integration_tests")]
+    use super::dispatch_webhook;
+    use crate::config::{CustomWebhookFormat, Http, HttpMethod, PagerDutySeverity};
+    use indexmap::IndexMap;
+
+    #[cfg(feature = "integration_tests")]
+    const MESSAGE: &str = "noti test execution.";
+
+    /// A plain text webhook destination sending to `url`.
+    fn webhook(url: &str) -> Destination {
+        DestinationKind::Webhook {
+            url: url.into(),
+            format: WebhookFormat::Standard(StandardWebhookFormat::PlainText),
+            title: None,
+        }
+        .into()
+    }
+
+    #[tokio::test]
+    pub async fn add_default_destination_test() -> Result<()> {
+        let dir = TempDir::new("add_default_destination_test")?;
+        let temp_cfg = dir.join("noti.yaml");
+
+        init(&temp_cfg, &DestinationType::Desktop, false, false).await?;
+        add_default_destination(&temp_cfg, &DestinationType::Desktop, false).await?;
+
+        let config = Config::try_from(&temp_cfg)?;
+        assert_eq!(config.destination.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn toml_config_round_trips_test() -> Result<()> {
+        let dir = TempDir::new("toml_config_round_trips_test")?;
+        let temp_cfg = dir.join("noti.toml");
+
+        init(&temp_cfg, &DestinationType::Webhook, true, false).await?;
+        add_default_destination(&temp_cfg, &DestinationType::Desktop, false).await?;
+
+        let content = tokio::fs::read_to_string(&temp_cfg).await?;
+        assert!(content.contains("[[destination]]"));
+
+        let config = Config::parse(&content, ConfigFormat::Toml, true)?;
+        let kinds: Vec<_> = config.destination.iter().map(Destination::kind).collect();
+        assert_eq!(kinds, ["desktop", "webhook"]);
+
+        let typo = "destination = []\n[stream]\nenabled = true\nmathcing = \"ERROR\"\n";
+        assert!(Config::parse(typo, ConfigFormat::Toml, true).is_err_and(
+            |e| matches!(e, Error::UnknownConfigKeys { keys } if keys == ["stream.mathcing"])
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn add_default_destination_fails_with_no_config_test() -> Result<()> {
+        let dir = TempDir::new("add_default_destination_fails_with_no_config_test")?;
+        let temp_cfg = dir.join("noti.yaml");
+        let res = add_default_destination(&temp_cfg, &DestinationType::Desktop, false).await;
+        assert!(res.is_err_and(|e| matches!(e, Error::NoConfig)));
+        Ok(())
+    }
+
+    #[test]
+    pub fn validate_config_reports_problems_test() {
+        let mut config = Config {
+            destination: vec![
+                webhook("https://example.com/hooks/$(service)"),
+                Destination::default_custom_webhook(),
+                Destination::default_email(),
+            ],
+            ..Default::default()
+        };
+        assert!(validate_config(&config).is_ok());
+
+        config.stream.matching = Some(Matching::One("(unclosed".into()));
+        config.destination[0] = webhook("not a url");
+        if let DestinationKind::Webhook {
+            format: WebhookFormat::Custom(format),
+            ..
+        } = &mut config.destination[1].kind
+        {
+            format.http.headers.insert("Bad Header".into(), "x".into());
+        }
+        config.destination[2].fallback = Some(Box::new(webhook("://")));
+
+        assert!(validate_config(&config)
+            .is_err_and(|e| matches!(e, Error::ValidationFailed { failed: 4 })));
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_fits_messages_to_max_length_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let discord = Destination::from(DestinationKind::Webhook {
+            url: server.url.clone(),
+            format: WebhookFormat::Standard(StandardWebhookFormat::Discord),
+            title: None,
+        });
+        let split = Destination {
+            max_length: Some(1000),
+            overflow: Overflow::Split,
+            ..webhook(&server.url)
+        };
+        let config = Config {
+            destination: vec![discord, split],
+            max_concurrency: Some(1),
+            ..Default::default()
+        };
+        let message = format!("{}\n{}", "a".repeat(1500), "b".repeat(1500));
+
+        dispatch_all(&message, &Vars::new(), None, &config, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        let content: serde_json::Value = serde_json::from_str(&bodies[0]).unwrap();
+        let content = content["content"].as_str().unwrap();
+        assert_eq!(content.chars().count(), 2000);
+        assert!(content.ends_with("b…"));
+        let lengths: Vec<usize> = bodies[1..].iter().map(String::len).collect();
+        assert_eq!(lengths, [1000, 500, 1000, 500]);
+        Ok(())
+    }
+
+    #[test]
+    pub fn has_display_test() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.into())
+            }
+        };
+
+        assert!(has_display(env(&[("DISPLAY", ":0")])));
+        assert!(has_display(env(&[("WAYLAND_DISPLAY", "wayland-0")])));
+        assert!(!has_display(env(&[
+            ("DISPLAY", ":0"),
+            ("SSH_CONNECTION", "10.0.0.1 22")
+        ])));
+        assert!(!has_display(env(&[
+            ("DISPLAY", ":0"),
+            ("SSH_TTY", "/dev/pts/1")
+        ])));
+        #[cfg(all(unix, not(target_os = "macos")))]
+        assert!(!has_display(env(&[("DISPLAY", "")])));
+    }
+
+    #[tokio::test]
+    pub async fn auto_sends_to_headless_without_display_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let desktop = Destination::default_desktop();
+        let headless = webhook(&server.url);
+        let ssh = |name: &str| {
+            (name == "SSH_CONNECTION")
+                .then(|| std::ffi::OsString::from("10.0.0.1 50000 10.0.0.2 22"))
+        };
+
+        let target = auto_target(&desktop, &headless, ssh);
+        assert_eq!(target.kind(), "webhook");
+        dispatch("deployed", &Vars::new(), target, &RequestOptions::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["deployed"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dry_run_sends_nothing_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url), Destination::default_desktop()],
+            dry_run: true,
+            ..Default::default()
+        };
+
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+        assert!(server.requests().is_empty());
+
+        let request = reqwest::Client::new()
+            .post("https://discord.com/api/webhooks/1/secret")
+            .header("Content-Type", "application/json")
+            .body(r#"{"content":"hello"}"#)
+            .build()?;
+        assert_eq!(
+            describe_request(&request, false),
+            "Would send POST https://discord.com/***\n\
+             content-type: application/json\n\
+             {\"content\":\"hello\"}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn mask_message_hides_only_output_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![Destination {
+                sensitive: true,
+                ..webhook(&server.url)
+            }],
+            ..Default::default()
+        };
+
+        dispatch_all(
+            "password: hunter2",
+            &Vars::new(),
+            None,
+            &config,
+            &Status::default(),
+        )
+        .await?;
+        assert_eq!(server.requests()[0].body_text(), "password: hunter2");
+
+        let request = reqwest::Client::new()
+            .post(&server.url)
+            .body("password: hunter2")
+            .build()?;
+        let description = describe_request(&request, true);
+        assert!(description.ends_with("\n[masked, 17 bytes]"));
+        assert!(!description.contains("hunter2"));
+        assert_eq!(masked("hunter2", false), "hunter2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn test_destinations_bounds_concurrency_test() -> Result<()> {
+        let server = MockServer::start_delayed(Duration::from_millis(50)).await;
+        let destination = (0..6).map(|_| webhook(&server.url)).collect();
+        let config = Config {
+            destination,
+            max_concurrency: Some(2),
+            ..Default::default()
+        };
+
+        test_destinations(&config).await?;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 6);
+        assert!(requests
+            .iter()
+            .all(|r| r.method == "POST" && r.body_text() == super::TEST_MESSAGE));
+        assert_eq!(requests[0].header("content-type"), Some("text/plain"));
+        assert!(server.max_in_flight() <= 2);
+        Ok(())
+    }
+
+    #[test]
+    pub fn version_json_includes_version_test() {
+        let json = serde_json::to_value(VersionInfo::current()).unwrap();
+
+        assert_eq!(json["name"], "noti");
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(json["commit"].is_string());
+        assert!(json["features"].is_array());
+    }
+
+    #[test]
+    pub fn bash_completions_test() -> Result<()> {
+        let mut script = vec![];
+        completions(clap_complete::Shell::Bash, &mut script)?;
+        let script = String::from_utf8(script).unwrap();
+
+        assert!(!script.is_empty());
+        assert!(script.contains("complete -F _noti"));
+        assert!(script.contains("--message-file"));
+        assert!(Cli::try_parse_from(["noti", "completions", "zsh"]).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    pub fn check_destinations_on_empty_test() {
+        let config = Config::default();
+
+        assert!(check_destinations(&config, OnEmpty::Warn).is_ok());
+        assert!(check_destinations(&config, OnEmpty::Error)
+            .is_err_and(|e| matches!(e, Error::NoDestinations)));
+        assert!(check_destinations(&Config::default_desktop(), OnEmpty::Error).is_ok());
+    }
+
+    #[test]
+    pub fn progress_capture_sets_hint_test() -> Result<()> {
+        let patterns = [Regex::new(r"^(?P<progress>[0-9.]+%?) done")?];
+        let progress = |line| {
+            let (_, vars) = match_line(line, &patterns, false, false).remove(0);
+            progress_hint(&vars)
+        };
+
+        assert_eq!(
+            progress("42% done"),
+            Some(notify_rust::Hint::CustomInt("value".into(), 42))
+        );
+        assert_eq!(
+            progress("140 done"),
+            Some(notify_rust::Hint::CustomInt("value".into(), 100))
+        );
+        assert_eq!(progress(". done"), None);
+        assert_eq!(progress_hint(&Vars::new()), None);
+        Ok(())
+    }
+
+    #[test]
+    pub fn match_line_requires_all_patterns_test() -> Result<()> {
+        let patterns = compile(&Matching::Many(vec!["ERROR".into(), "payment".into()]))?;
+
+        let messages = |line, match_all| -> Result<Vec<String>> {
+            Ok(match_line(line, &patterns, match_all, false)
+                .into_iter()
+                .map(|(msg, _)| msg)
+                .collect())
+        };
+
+        assert_eq!(
+            messages("ERROR: payment failed", true)?,
+            ["ERROR: payment failed"]
+        );
+        assert!(messages("ERROR: disk full", true)?.is_empty());
+        assert!(messages("payment received", true)?.is_empty());
+        assert_eq!(messages("payment received", false)?, ["payment"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_global_dispatches_each_match_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                matching: Some(Matching::One(r"ERROR: \w+".into())),
+                redirect: None,
+                global: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "ERROR: disk ERROR: network\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["ERROR: disk", "ERROR: network"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_substitutes_captures_in_url_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&format!("{}/hooks/$(service)", server.url))],
+            stream: Stream {
+                enabled: true,
+                matching: Some(Matching::One("^(?P<service>[^:]+): .*$".into())),
+                redirect: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "billing/api: down\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/hooks/billing%2Fapi"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_forwards_json_lines_unchanged_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                template: Some("ignored: $(message)".into()),
+                json_line: true,
+                json_filter: IndexMap::from([("level".to_string(), "error".to_string())]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let line = r#"{"level": "error", "msg": "disk full",  "code": 507}  "#;
+        let input =
+            format!("{line}\n{{\"level\": \"info\", \"msg\": \"ok\"}}\nnot json\n[\"error\"]\n");
+
+        stream_and_dispatch(
+            std::io::Cursor::new(input),
+            &config,
+            None,
+            &Status::default(),
+        )
+        .await?;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].body_text(), line);
+        assert_eq!(requests[0].header("content-type"), Some("application/json"));
+        Ok(())
+    }
+
+    #[test]
+    pub fn json_line_matches_filter_test() {
+        let filter = IndexMap::from([
+            ("level".to_string(), "error".to_string()),
+            ("code".to_string(), "500".to_string()),
+        ]);
+
+        assert!(json_line_matches(
+            r#"{"level":"error","code":500}"#,
+            &filter
+        ));
+        assert!(!json_line_matches(
+            r#"{"level":"error","code":"501"}"#,
+            &filter
+        ));
+        assert!(!json_line_matches(r#"{"level":"error"}"#, &filter));
+        assert!(json_line_matches(r#"{"any":true}"#, &IndexMap::new()));
+        assert!(!json_line_matches("[1, 2]", &IndexMap::new()));
+    }
+
+    #[tokio::test]
+    pub async fn stream_substitutes_captures_in_template_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![DestinationKind::Webhook {
+                url: server.url.clone(),
+                title: None,
+                format: WebhookFormat::Custom(CustomWebhookFormat {
+                    http: Http {
+                        headers: IndexMap::from([(
+                            "Content-Type".into(),
+                            "application/json".into(),
+                        )]),
+                        method: HttpMethod::POST,
+                    },
+                    template: r#"{"level":"$(level)","code":"$(code)","msg":"$(msg)"}"#.into(),
+                    escape: false,
+                    auth: None,
+                    timeout_secs: None,
+                    response_log: None,
+                    gzip: false,
+                }),
+            }
+            .into()],
+            stream: Stream {
+                enabled: true,
+                matching: Some(Matching::One(
+                    r"^(?P<level>WARN|ERROR):(?: \[(?P<code>E\d+)\])? (?P<msg>.*)$".into(),
+                )),
+                redirect: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "ERROR: [E42] disk \"full\"\nWARN: slow\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(
+            bodies,
+            [
+                r#"{"level":"ERROR","code":"E42","msg":"disk \"full\""}"#,
+                r#"{"level":"WARN","code":"","msg":"slow"}"#,
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_continues_on_error_test() -> Result<()> {
+        let server = MockServer::start_replying(vec![Reply::status(500)]).await;
+        let mut config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                on_error: OnError::Continue,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let status = Status::default();
+        stream_and_dispatch("one\ntwo\n".as_bytes(), &config, None, &status).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["one", "two"]);
+
+        let server = MockServer::start_replying(vec![Reply::status(500)]).await;
+        config.destination = vec![webhook(&server.url)];
+        config.stream.on_error = OnError::Stop;
+        let result = stream_and_dispatch("one\ntwo\n".as_bytes(), &config, None, &status).await;
+        assert!(result.is_err());
+        assert_eq!(server.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_caps_notifications_per_minute_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                ..Default::default()
+            },
+            max_per_minute: Some(2),
+            ..Default::default()
+        };
+        let input = "one\ntwo\nthree\nfour\nfive\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(
+            bodies,
+            [
+                "one",
+                "two",
+                "Suppressed 3 notification(s) over the limit of 2 per minute"
+            ]
+        );
+        Ok(())
+    }
+
+    /// Input which yields each chunk after its delay.
+    struct SlowInput(Vec<(Duration, &'static [u8])>);
+
+    impl std::io::Read for SlowInput {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let (delay, chunk) = self.0.remove(0);
+            std::thread::sleep(delay);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    #[tokio::test]
+    pub async fn stream_throttle_coalesces_messages_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                throttle_ms: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = SlowInput(vec![
+            (Duration::ZERO, b"a\nb\n"),
+            (Duration::from_millis(400), b"c\n"),
+        ]);
+
+        stream_and_dispatch(
+            std::io::BufReader::new(input),
+            &config,
+            None,
+            &Status::default(),
+        )
+        .await?;
+
+        // `c` is flushed once the input ends, before its window is up.
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["a\nb", "c"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_batches_messages_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                batch_size: Some(2),
+                batch_timeout_ms: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = SlowInput(vec![
+            (Duration::ZERO, b"a\nb\nc\n"),
+            (Duration::from_millis(400), b"d\n"),
+        ]);
+
+        stream_and_dispatch(
+            std::io::BufReader::new(input),
+            &config,
+            None,
+            &Status::default(),
+        )
+        .await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["a\nb", "c", "d"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_batch_count_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config: Config = serde_yaml::from_str(&format!(
+            "stream:\n  \
+               enabled: true\n  \
+               batch_size: 3\n\
+             destination:\n\
+             - type: webhook\n  \
+               url: {}\n  \
+               format:\n    \
+                 http:\n      headers: {{}}\n      method: POST\n    \
+                 template: \"$(count) new errors\\n$(message)\"\n    \
+                 escape: false\n",
+            server.url
+        ))?;
+        let config = Config {
+            stream: Stream {
+                redirect: None,
+                ..config.stream
+            },
+            ..config
+        };
+
+        stream_and_dispatch(
+            std::io::Cursor::new(b"a\nb\nc\n".to_vec()),
+            &config,
+            None,
+            &Status::default(),
+        )
+        .await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["3 new errors\na\nb\nc"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_batch_separator_and_prefix_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                buffer_all: true,
+                batch_separator: Some("\n---\n".into()),
+                batch_line_prefix: Some("- ".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        stream_and_dispatch("a\nb\nc\n".as_bytes(), &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["- a\n---\n- b\n---\n- c"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_buffer_all_sends_once_at_eof_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                matching: Some(Matching::One("^ERROR.*$".into())),
+                redirect: None,
+                buffer_all: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "ERROR: a\ninfo\nERROR: b\nERROR: c\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["ERROR: a\nERROR: b\nERROR: c"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_skips_blank_lines_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "\n   \nhello\n\t\r\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["hello"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_trims_messages_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let mut config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "  ERROR: x \t\r\r\n";
+
+        stream_and_dispatch(input.as_bytes(), &config, None, &Status::default()).await?;
+        config.stream.trim = Trim::Both;
+        stream_and_dispatch(input.as_bytes(), &config, None, &Status::default()).await?;
+        config.stream.trim = Trim::None;
+        stream_and_dispatch(input.as_bytes(), &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["  ERROR: x", "ERROR: x", "  ERROR: x \t\r"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_rejects_invalid_pattern_up_front_test() -> Result<()> {
+        let config = Config {
+            destination: vec![Destination::default_desktop()],
+            stream: Stream {
+                enabled: true,
+                matching: Some(Matching::One("(unclosed".into())),
+                redirect: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let result = stream_and_dispatch("".as_bytes(), &config, None, &Status::default()).await;
+
+        assert!(result.is_err_and(|e| matches!(e, Error::Regex(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_destination_matching_test() -> Result<()> {
+        let errors = MockServer::start().await;
+        let everything = MockServer::start().await;
+        let config = Config {
+            destination: vec![
+                Destination {
+                    matching: Some(Matching::One("^ERROR.*$".into())),
+                    ..webhook(&errors.url)
+                },
+                webhook(&everything.url),
+            ],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "info line\nERROR: x\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies = |server: &MockServer| -> Vec<String> {
+            server.requests().iter().map(|r| r.body_text()).collect()
+        };
+        assert_eq!(bodies(&errors), ["ERROR: x"]);
+        assert_eq!(bodies(&everything), ["info line", "ERROR: x"]);
+        Ok(())
+    }
+
+    #[test]
+    pub fn desktop_summary_from_level_capture_test() {
+        let re = Regex::new(r"^(?P<level>WARN|ERROR): (?P<msg>.*)$").unwrap();
+        let captures = re.captures("ERROR: disk full").unwrap();
+        let vars = named_captures(&re, &captures);
+
+        assert_eq!(desktop_summary("Noti", Some("level"), &vars), "ERROR");
+        assert_eq!(desktop_summary("Noti", Some("missing"), &vars), "Noti");
+        assert_eq!(desktop_summary("Noti", None, &vars), "Noti");
+    }
+
+    #[tokio::test]
+    pub async fn stream_dedupes_by_key_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                matching: Some(Matching::One(r"^(?P<error_code>E\d+) at \S+$".into())),
+                match_all: false,
+                redirect: None,
+                dedupe: true,
+                dedupe_key: Some("$(error_code)".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "E42 at 10:00\nE42 at 10:05\nE7 at 10:06\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["E42 at 10:00", "E7 at 10:06"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_dedupe_key_expires_after_ttl_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                matching: Some(Matching::One(r"^(?P<error_code>E\d+) at \S+$".into())),
+                redirect: None,
+                dedupe: true,
+                dedupe_key: Some("$(error_code)".into()),
+                dedupe_ttl_ms: Some(200),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = SlowInput(vec![
+            (Duration::ZERO, b"E42 at 10:00\nE42 at 10:01\n"),
+            (Duration::from_millis(400), b"E42 at 10:30\n"),
+        ]);
+
+        stream_and_dispatch(
+            std::io::BufReader::new(input),
+            &config,
+            None,
+            &Status::default(),
+        )
+        .await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["E42 at 10:00", "E42 at 10:30"]);
+        Ok(())
+    }
+
+    #[test]
+    pub fn first_sighting_prunes_expired_keys_test() {
+        let mut seen = HashMap::new();
+        let start = tokio::time::Instant::now();
+        let ttl = Some(Duration::from_secs(10));
+
+        assert!(first_sighting(&mut seen, "E1", start, ttl));
+        assert!(first_sighting(&mut seen, "E2", start, ttl));
+        assert!(!first_sighting(
+            &mut seen,
+            "E1",
+            start + Duration::from_secs(5),
+            ttl
+        ));
+        assert!(first_sighting(
+            &mut seen,
+            "E3",
+            start + Duration::from_secs(12),
+            ttl
+        ));
+        assert_eq!(seen.len(), 1);
+
+        assert!(first_sighting(&mut seen, "E1", start, None));
+        assert!(!first_sighting(
+            &mut seen,
+            "E1",
+            start + Duration::from_secs(60),
+            None
+        ));
+    }
+
+    #[tokio::test]
+    pub async fn stream_heartbeat_when_nothing_matches_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                matching: Some(Matching::One("^ERROR".into())),
+                heartbeat_secs: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = SlowInput(vec![
+            (Duration::ZERO, b"ERROR: a\n"),
+            (Duration::from_millis(700), b"INFO: b\n"),
+            (Duration::from_millis(800), b"ERROR: c\n"),
+        ]);
+
+        stream_and_dispatch(
+            std::io::BufReader::new(input),
+            &config,
+            None,
+            &Status::default(),
+        )
+        .await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(
+            bodies,
+            [
+                "ERROR",
+                "Still running, nothing matched in the last 1s",
+                "ERROR"
+            ]
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn follow_reads_appended_and_rotated_lines_test() -> Result<()> {
+        use std::io::Write;
+
+        let dir = TempDir::new("follow_reads_appended_and_rotated_lines_test")?;
+        let path = dir.join("noti.log");
+        std::fs::write(&path, "old\n")?;
+        let follow = Follow::open(&path)?;
+        let writer = std::thread::spawn({
+            let path = path.clone();
+            move || -> std::io::Result<()> {
+                std::thread::sleep(Duration::from_millis(100));
+                let mut file = std::fs::OpenOptions::new().append(true).open(&path)?;
+                file.write_all(b"new\n")?;
+                std::thread::sleep(Duration::from_millis(500));
+                std::fs::remove_file(&path)?;
+                std::fs::write(&path, "rotated\n")
+            }
+        });
+
+        let lines = lossy_lines(std::io::BufReader::new(follow))
+            .take(2)
+            .collect::<std::io::Result<Vec<_>>>();
+        writer.join().unwrap()?;
+        assert_eq!(lines?, ["new", "rotated"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_digest_sends_on_each_tick_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                digest_interval_ms: Some(300),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = SlowInput(vec![
+            (Duration::ZERO, b"a\nb\n"),
+            (Duration::from_millis(500), b"c\n"),
+            (Duration::from_millis(500), b"d\n"),
+        ]);
+
+        stream_and_dispatch(
+            std::io::BufReader::new(input),
+            &config,
+            None,
+            &Status::default(),
+        )
+        .await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["a\nb", "c", "d"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn execute_substitutes_exit_code_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let dir = TempDir::new("execute_substitutes_exit_code_test")?;
+        let config_path = dir.join("noti.yaml");
+        let path = config_path.to_str().unwrap();
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            ..Default::default()
+        };
+        tokio::fs::write(path, serde_yaml::to_string(&config)?).await?;
+
+        let args = Cli::try_parse_from([
+            "noti",
+            "--config",
+            path,
+            "--exit-code",
+            "2",
+            "build exited with $(exit_code)",
+        ])
+        .unwrap();
+        execute(args, Arc::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["build exited with 2"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn execute_substitutes_config_path_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let dir = TempDir::new("execute_substitutes_config_path_test")?;
+        let config_path = dir.join("noti.yaml");
+        let path = config_path.to_str().unwrap();
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            ..Default::default()
+        };
+        tokio::fs::write(path, serde_yaml::to_string(&config)?).await?;
+
+        let args =
+            Cli::try_parse_from(["noti", "--config", path, "alert (from $(config_path))"]).unwrap();
+        execute(args, Arc::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, [format!("alert (from {path})")]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn execute_records_http_status_of_failures_test() -> Result<()> {
+        let ok = MockServer::start().await;
+        let failing = MockServer::start_replying(vec![Reply::status(500)]).await;
+        let dir = TempDir::new("execute_records_http_status_of_failures_test")?;
+        let config_path = dir.join("noti.yaml");
+        let path = config_path.to_str().unwrap();
+        let config = Config {
+            destination: vec![webhook(&ok.url), webhook(&failing.url)],
+            ..Default::default()
+        };
+        tokio::fs::write(path, serde_yaml::to_string(&config)?).await?;
+
+        let status = Arc::new(Status::default());
+        let args =
+            Cli::try_parse_from(["noti", "--config", path, "--output", "json", "hi"]).unwrap();
+        let result = execute(args, status.clone()).await;
+
+        let report: serde_json::Value = serde_json::from_str(&status.report(&result)).unwrap();
+        assert_eq!(report["ok"], false);
+        let mut dispatches = report["dispatches"].as_array().unwrap().clone();
+        dispatches.sort_by_key(|dispatch| dispatch["destination"].as_u64());
+        assert_eq!(dispatches[0]["ok"], true);
+        assert_eq!(dispatches[1]["ok"], false);
+        assert_eq!(dispatches[1]["status"], 500);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn execute_joins_messages_into_lines_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let dir = TempDir::new("execute_joins_messages_into_lines_test")?;
+        let config_path = dir.join("noti.yaml");
+        let path = config_path.to_str().unwrap();
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            ..Default::default()
+        };
+        tokio::fs::write(path, serde_yaml::to_string(&config)?).await?;
+
+        let args = Cli::try_parse_from(["noti", "--config", path, "line1", "line2"]).unwrap();
+        execute(args, Arc::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["line1\nline2"]);
+        let single = Cli::try_parse_from(["noti", "one line"]).unwrap();
+        assert_eq!(single.message().as_deref(), Some("one line"));
+        assert!(Cli::try_parse_from(["noti", "test"])
+            .unwrap()
+            .command
+            .is_some());
+        Ok(())
+    }
+
+    #[test]
+    pub fn message_to_send_substitutes_stdin_test() -> Result<()> {
+        let args = Cli::try_parse_from(["noti", "--stdin", "--exit-code", "2"]).unwrap();
+        let vars = Vars::from([("exit_code".to_string(), "2".to_string())]);
+
+        let message = message_to_send(
+            &args,
+            &Config::default(),
+            &vars,
+            "build exited with $(exit_code)\n".as_bytes(),
+        )?;
+        assert_eq!(message.as_deref(), Some("build exited with 2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn execute_reads_message_file_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let dir = TempDir::new("execute_reads_message_file_test")?;
+        let config_path = dir.join("noti.yaml");
+        let path = config_path.to_str().unwrap();
+        let message_file = dir.join("message.txt");
+        let message_path = message_file.to_str().unwrap();
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            ..Default::default()
+        };
+        tokio::fs::write(path, serde_yaml::to_string(&config)?).await?;
+        tokio::fs::write(message_path, "line one\nline $(two)\n").await?;
+
+        let parse = |extra: &[&str]| {
+            let args = ["noti", "--config", path, "--message-file", message_path];
+            Cli::try_parse_from(args.iter().chain(extra)).unwrap()
+        };
+        let sent = execute(parse(&["ignored"]), Arc::default()).await;
+        let mut streaming = parse(&[]);
+        streaming.input_json_line = true;
+        let streamed = execute(streaming, Arc::default()).await;
+        let missing = execute(
+            Cli::try_parse_from([
+                "noti",
+                "--config",
+                path,
+                "--message-file",
+                dir.join("missing.txt").to_str().unwrap(),
+            ])
+            .unwrap(),
+            Arc::default(),
+        )
+        .await;
+        sent?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["line one\nline $(two)"]);
+        assert!(matches!(streamed, Err(Error::StreamAndMessage)));
+        assert!(matches!(missing, Err(Error::MessageFile { .. })));
+        assert!(Cli::try_parse_from(["noti", "--stdin", "--message-file", "-"]).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn execute_reports_invalid_config_test() -> Result<()> {
+        let dir = TempDir::new("execute_reports_invalid_config_test")?;
+        let config_path = dir.join("noti.yaml");
+        let path = config_path.to_str().unwrap();
+        tokio::fs::write(path, "destination: not a list\n").await?;
+
+        let args = Cli::try_parse_from(["noti", "--config", path, "hello"]).unwrap();
+        let error = execute(args, Arc::default()).await.unwrap_err();
+        let mut shown = None;
+        assert!(report_invalid_config(&error, |summary, _| {
+            shown = Some(summary.to_string())
+        }));
+        assert_eq!(shown.as_deref(), Some("noti config invalid"));
+        assert!(!report_invalid_config(&Error::NoMessage, |_, _| {
+            panic!("only config errors are reported")
+        }));
+        Ok(())
+    }
+
+    #[test]
+    pub fn format_elapsed_test() {
+        assert_eq!(format_elapsed(Duration::from_millis(900)), "0s");
+        assert_eq!(format_elapsed(Duration::from_secs(192)), "3m12s");
+        assert_eq!(format_elapsed(Duration::from_secs(3723)), "1h2m3s");
+    }
+
+    #[tokio::test]
+    pub async fn run_reports_exit_status_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let dir = TempDir::new("run_reports_exit_status_test")?;
+        let path = dir.join("noti.yaml");
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            ..Default::default()
+        };
+        tokio::fs::write(&path, serde_yaml::to_string(&config)?).await?;
+
+        let command = ["sh", "-c", "exit 3"].map(String::from);
+        let status = Status::default();
+        let result = run(
+            std::slice::from_ref(&path),
+            &[],
+            &command,
+            None,
+            None,
+            &status,
+        )
+        .await;
+        result?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["sh -c exit 3 finished in 0s (exit 3)"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_template_includes_previous_line_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            stream: Stream {
+                enabled: true,
+                matching: Some(Matching::One("^ERROR.*$".into())),
+                redirect: None,
+                template: Some("$(message) (after: $(prev_line))".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input = "connecting to db\nERROR: timed out\n".as_bytes();
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["ERROR: timed out (after: connecting to db)"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn stream_substitutes_invalid_utf8_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![DestinationKind::Webhook {
+                url: server.url.clone(),
+                title: None,
+                format: WebhookFormat::Custom(CustomWebhookFormat {
+                    http: Http {
+                        headers: IndexMap::from([("Content-Type".into(), "text/plain".into())]),
+                        method: HttpMethod::POST,
+                    },
+                    template: "got: $(message)".into(),
+                    escape: false,
+                    auth: None,
+                    timeout_secs: None,
+                    response_log: None,
+                    gzip: false,
+                }),
+            }
+            .into()],
+            stream: Stream {
+                enabled: true,
+                redirect: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let input: &[u8] = b"bad \xff byte\r\nfine\n";
+
+        stream_and_dispatch(input, &config, None, &Status::default()).await?;
+
+        let bodies: Vec<String> = server.requests().iter().map(|r| r.body_text()).collect();
+        assert_eq!(bodies, ["got: bad \u{fffd} byte", "got: fine"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn read_message_sends_one_aggregated_message_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            ..Default::default()
+        };
+
+        let message = read_message("first\nsecond\nthird\n".as_bytes(), 1024)?;
+        dispatch_all(&message, &Vars::new(), None, &config, &Status::default()).await?;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].body_text(), "first\nsecond\nthird");
+        assert_eq!(read_message("first\nsecond".as_bytes(), 5)?, "first");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn send_telegram_test() -> Result<()> {
+        let server = MockServer::start().await;
+
+        send_telegram(
+            &server.url,
+            "hello",
+            "TOKEN",
+            "42",
+            &RequestOptions::default(),
+        )
+        .await?;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/botTOKEN/sendMessage");
+        assert_eq!(requests[0].header("content-type"), Some("application/json"));
+        assert_eq!(
+            requests[0].body_text(),
+            r#"{"chat_id":"42","text":"hello"}"#
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn follow_redirects_test() -> Result<()> {
+        let redirect = Reply::status(302).header("Location", "/login");
+        let server =
+            MockServer::start_replying(vec![redirect.clone(), Reply::status(200), redirect]).await;
+        let mut config = Config {
+            destination: vec![webhook(&server.url)],
+            ..Default::default()
+        };
 
-    const MESSAGE: &str = "noti test execution.";
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(server.requests()[1].path, "/login");
+
+        config.destination[0].follow_redirects = false;
+        let result = dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await;
+        assert!(result.is_err_and(|e| matches!(e, Error::Redirected { status: 302 })));
+        assert_eq!(server.requests().len(), 3);
+        Ok(())
+    }
 
     #[tokio::test]
-    pub async fn add_default_destination_test() -> Result<()> {
-        let temp_cfg = PathBuf::from("add_default_destination_test_noti.yaml");
+    pub async fn redact_url_test() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
 
-        init(&temp_cfg, &DestinationType::Desktop, false).await?;
-        add_default_destination(&temp_cfg, &DestinationType::Desktop, false).await?;
+        let error = reqwest::Client::new()
+            .get(format!("http://{addr}/hooks/secret"))
+            .send()
+            .await
+            .unwrap_err();
+        let redacted = redact_url(&error);
+        assert!(redacted.contains(&format!("http://{addr}/***")));
+        assert!(!redacted.contains("secret"));
+    }
 
-        let config = Config::try_from(&temp_cfg)?;
-        assert_eq!(config.destination.len(), 2);
+    /// Collects what's logged, to check what verbose mode shows.
+    #[derive(Clone, Default)]
+    struct LogCapture(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl LogCapture {
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    impl std::io::Write for LogCapture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for LogCapture {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self {
+            self.clone()
+        }
+    }
+
+    #[test]
+    pub fn verbose_flag_test() {
+        let verbose = |args: &[&str]| Cli::try_parse_from(args).unwrap().verbose;
+
+        assert_eq!(verbose(&["noti", "hello"]), 0);
+        assert_eq!(verbose(&["noti", "-v", "hello"]), 1);
+        assert_eq!(verbose(&["noti", "-vv", "hello"]), 2);
+        assert_eq!(verbose(&["noti", "--verbose", "-v", "-v", "hello"]), 3);
+    }
 
-        tokio::fs::remove_file(&temp_cfg).await?;
+    #[tokio::test]
+    pub async fn verbose_logs_sends_and_retries_test() -> Result<()> {
+        let server = MockServer::start_replying(vec![Reply::status(500), Reply::status(200)]).await;
+        let config = Config {
+            destination: vec![Destination {
+                name: Some("team".into()),
+                ..webhook(&server.url)
+            }],
+            retry: Some(Retry {
+                max_attempts: 3,
+                initial_delay_ms: 1,
+            }),
+            ..Default::default()
+        };
+        let log = LogCapture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(log.clone())
+            .with_ansi(false)
+            .with_max_level(tracing::Level::INFO)
+            .finish();
+
+        let _guard = tracing::subscriber::set_default(subscriber);
+        dispatch_all("deployed", &Vars::new(), None, &config, &Status::default()).await?;
+
+        let log = log.text();
+        assert!(log.contains("Sending to webhook `team`"));
+        assert!(log.contains("Got response 500 Internal Server Error"));
+        assert!(log.contains("attempt 2 of 3"));
+        assert!(log.contains("Got response 200 OK"));
         Ok(())
     }
 
     #[tokio::test]
-    pub async fn add_default_destination_fails_with_no_config_test() -> Result<()> {
-        let temp_cfg = PathBuf::from("add_default_destination_fails_with_no_config_test_noti.yaml");
-        let res = add_default_destination(&temp_cfg, &DestinationType::Desktop, false).await;
-        assert!(res.is_err_and(|e| matches!(e, Error::NoConfig)));
+    pub async fn verbose_logs_attempts_and_backoff_test() -> Result<()> {
+        let server = MockServer::start_replying(vec![
+            Reply::status(500),
+            Reply::status(503),
+            Reply::status(200),
+        ])
+        .await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            retry: Some(Retry {
+                max_attempts: 3,
+                initial_delay_ms: 1,
+            }),
+            ..Default::default()
+        };
+        let log = LogCapture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(log.clone())
+            .with_ansi(false)
+            .with_max_level(tracing::Level::INFO)
+            .finish();
+
+        let _guard = tracing::subscriber::set_default(subscriber);
+        dispatch_all("deployed", &Vars::new(), None, &config, &Status::default()).await?;
+
+        assert!(log
+            .text()
+            .contains("Finished after 3 attempts, waiting 3ms in total"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn charset_content_type_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let discord = Destination::from(DestinationKind::Webhook {
+            url: server.url.clone(),
+            format: WebhookFormat::Standard(StandardWebhookFormat::Discord),
+            title: None,
+        });
+        let config = Config {
+            destination: vec![
+                discord.clone(),
+                Destination {
+                    charset: Some("utf-8".into()),
+                    ..discord
+                },
+            ],
+            max_concurrency: Some(1),
+            ..Default::default()
+        };
+
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+        let content_types: Vec<_> = server
+            .requests()
+            .iter()
+            .map(|r| r.header("content-type").map(str::to_string))
+            .collect();
+        assert_eq!(
+            content_types,
+            [
+                Some("application/json".into()),
+                Some("application/json; charset=utf-8".into())
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_through_proxy_test() -> Result<()> {
+        let proxy = MockServer::start().await;
+        let config = Config {
+            destination: vec![webhook("http://noti.invalid/hook")],
+            proxy: Some(proxy.url.replace("http://", "http://user:pass@")),
+            ..Default::default()
+        };
+
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+        let requests = proxy.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "http://noti.invalid/hook");
+        assert_eq!(
+            requests[0].header("proxy-authorization"),
+            Some("Basic dXNlcjpwYXNz")
+        );
+        assert_eq!(requests[0].body_text(), "hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn preview_renders_each_sample_line_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config: Config = serde_yaml::from_str(&format!(
+            "stream:\n  \
+               enabled: true\n  \
+               matching: \"^(?P<level>WARN|ERROR): (?P<msg>.*)$\"\n  \
+               template: \"$(msg), after '$(prev_line)'\"\n  \
+               dedupe: true\n\
+             destination:\n\
+             - type: webhook\n  \
+               url: {}/hooks/$(level)\n  \
+               format: discord\n  \
+               title: $(level)\n\
+             - type: desktop\n  \
+               summary: Noti\n  \
+               persistent: false\n  \
+               matching: \"^(?P<level>ERROR): (?P<msg>.*)$\"\n",
+            server.url
+        ))?;
+        let sample = "WARN: disk 90% full\nall good\nERROR: disk full\nERROR: disk full\n";
+
+        let mut out = vec![];
+        preview_lines(config, sample.as_bytes(), &mut out).await?;
+        assert!(server.requests().is_empty());
+        let origin = server.url.as_str();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "line 1: WARN: disk 90% full\n  \
+                 Would send POST {origin}/***\n  \
+                 content-type: application/json\n  \
+                 {{\"embeds\":[{{\"description\":\"disk 90% full, after ''\",\"title\":\"WARN\"}}]}}\n\
+                 line 2: all good\n  \
+                 nothing sent\n\
+                 line 3: ERROR: disk full\n  \
+                 Would send POST {origin}/***\n  \
+                 content-type: application/json\n  \
+                 {{\"embeds\":[{{\"description\":\"disk full, after 'all good'\",\"title\":\"ERROR\"}}]}}\n  \
+                 Would send to desktop, titled \"Noti\"\n  \
+                 disk full, after 'all good'\n\
+                 line 4: ERROR: disk full\n  \
+                 nothing sent\n"
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dry_run_leaves_exec_placeholders_test() -> Result<()> {
+        let log = DryRunLog::default();
+        let config: Config = serde_yaml::from_str(
+            "allow_exec: true\n\
+             destination:\n\
+             - type: webhook\n  \
+               url: http://localhost\n  \
+               format:\n    \
+                 http:\n      headers: {}\n      method: POST\n    \
+                 template: \"$(message) by $(exec:echo ci)\"\n    \
+                 escape: false\n",
+        )?;
+        let config = Config {
+            dry_run: true,
+            dry_run_log: Some(log.clone()),
+            ..config
+        };
+
+        dispatch_all("deployed", &Vars::new(), None, &config, &Status::default()).await?;
+
+        let sent = &log.lock().unwrap().sent;
+        assert!(sent[0].1.ends_with("\ndeployed by $(exec:echo ci)"));
+        Ok(())
+    }
+
+    #[test]
+    pub fn http_client_tls_test() -> Result<()> {
+        let config: Config = serde_yaml::from_str(
+            "tls:\n  ca_cert: missing-ca.pem\n  accept_invalid_certs: true\n",
+        )?;
+        let options = config.request_options();
+        let tls = options.tls.as_ref().expect("Parsed above");
+        assert_eq!(tls.ca_cert, Some(PathBuf::from("missing-ca.pem")));
+        assert!(tls.accept_invalid_certs);
+
+        let result = http_client(None, &options);
+        assert!(result.is_err_and(|e| matches!(e, Error::CaCert { .. })));
+
+        let options = RequestOptions {
+            tls: Some(Tls {
+                ca_cert: None,
+                accept_invalid_certs: true,
+            }),
+            ..Default::default()
+        };
+        http_client(None, &options)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    pub async fn dispatch_exec_test() -> Result<()> {
+        let dir = TempDir::new("dispatch_exec_test")?;
+        let output = dir.join("output");
+        let exec = |args: &[&str]| Config {
+            destination: vec![DestinationKind::Exec {
+                command: "sh".into(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+                timeout_secs: None,
+            }
+            .into()],
+            ..Default::default()
+        };
+        let vars = Vars::from([("level".to_string(), "ERROR".to_string())]);
+        let script = r#"printf '%s|%s' "$NOTI_MESSAGE" "$1" > "$2""#;
+
+        let output_arg = output.to_str().unwrap();
+        let config = exec(&["-c", script, "sh", "[$(level)] $(message)", output_arg]);
+        dispatch_all("disk full", &vars, None, &config, &Status::default()).await?;
+        let written = std::fs::read_to_string(&output)?;
+        assert_eq!(written, "disk full|[ERROR] disk full");
+
+        let config = exec(&["-c", "exit 3"]);
+        let result = dispatch_all("disk full", &vars, None, &config, &Status::default()).await;
+        assert!(result.is_err_and(|e| matches!(e, Error::ExecFailed { code: Some(3), .. })));
+
+        let config = Config {
+            destination: vec![DestinationKind::Exec {
+                command: "noti-no-such-command".into(),
+                args: vec![],
+                timeout_secs: None,
+            }
+            .into()],
+            ..Default::default()
+        };
+        let result = dispatch_all("disk full", &vars, None, &config, &Status::default()).await;
+        assert!(result.is_err_and(|e| matches!(e, Error::Io(_))));
+
+        let config = Config {
+            destination: vec![DestinationKind::Exec {
+                command: "sleep".into(),
+                args: vec!["5".into()],
+                timeout_secs: Some(1),
+            }
+            .into()],
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+        let result = dispatch_all("disk full", &vars, None, &config, &Status::default()).await;
+        assert!(result.is_err_and(|e| matches!(e, Error::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(4));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_file_test() -> Result<()> {
+        let dir = TempDir::new("dispatch_file_test")?;
+        let path = dir.join("nested/noti.log");
+        let file = |append, format: Option<&str>| Config {
+            destination: vec![DestinationKind::File {
+                path: path.clone(),
+                append,
+                format: format.map(String::from),
+            }
+            .into()],
+            ..Default::default()
+        };
+        let vars = Vars::from([("level".to_string(), "ERROR".to_string())]);
+
+        dispatch_all("first", &vars, None, &file(true, None), &Status::default()).await?;
+        dispatch_all("second", &vars, None, &file(true, None), &Status::default()).await?;
+        let appended = std::fs::read_to_string(&path)?;
+        let overwrite = file(false, Some("[$(level)] $(message)"));
+        dispatch_all("third", &vars, None, &overwrite, &Status::default()).await?;
+        let truncated = std::fs::read_to_string(&path)?;
+
+        let lines: Vec<_> = appended.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let (timestamp, message) = lines[1].split_once(' ').unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+        assert_eq!(message, "second");
+        assert_eq!(truncated, "[ERROR] third\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    pub async fn dispatch_file_unwritable_path_test() -> Result<()> {
+        let dir = TempDir::new("dispatch_file_unwritable_path_test")?;
+        let path = dir.join("noti.log");
+        std::os::unix::fs::symlink("missing/noti.log", &path)?;
+        let config = Config {
+            destination: vec![DestinationKind::File {
+                path,
+                append: true,
+                format: None,
+            }
+            .into()],
+            ..Default::default()
+        };
+
+        let result = dispatch_all("first", &Vars::new(), None, &config, &Status::default()).await;
+        assert!(result
+            .is_err_and(|e| matches!(e, Error::Io(e) if e.kind() == std::io::ErrorKind::NotFound)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn send_pagerduty_test() -> Result<()> {
+        let server = MockServer::start_replying(vec![Reply::status(202)]).await;
+
+        send_pagerduty(
+            &format!("{}/v2/enqueue", server.url),
+            "build failed",
+            "KEY",
+            PagerDutySeverity::Critical,
+            &RequestOptions::default(),
+        )
+        .await?;
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/v2/enqueue");
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body["routing_key"], "KEY");
+        assert_eq!(body["event_action"], "trigger");
+        assert_eq!(body["payload"]["summary"], "build failed");
+        assert_eq!(body["payload"]["severity"], "critical");
+        assert_eq!(
+            body["payload"]["source"],
+            gethostname::gethostname().to_string_lossy().as_ref()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_all_times_out_test() -> Result<()> {
+        let server = MockServer::start_delayed(Duration::from_secs(5)).await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            timeout_secs: Some(1),
+            ..Default::default()
+        };
+
+        let result = dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await;
+
+        assert!(result.is_err_and(|e| matches!(e, Error::Timeout)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_all_retries_transient_failures_test() -> Result<()> {
+        let server = MockServer::start_replying(vec![
+            Reply::status(503),
+            Reply::status(429).header("Retry-After", "0"),
+        ])
+        .await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            retry: Some(Retry {
+                max_attempts: 3,
+                initial_delay_ms: 1,
+            }),
+            ..Default::default()
+        };
+
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+
+        assert_eq!(server.requests().len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_all_waits_out_rate_limits_test() -> Result<()> {
+        let server = MockServer::start_replying(vec![
+            Reply::status(429).header("Retry-After", "0"),
+            Reply::status(429).body(r#"{"retry_after": 0.2}"#),
+        ])
+        .await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            ..Default::default()
+        };
+
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+
+        assert_eq!(server.requests().len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_all_caps_rate_limit_wait_test() -> Result<()> {
+        let server =
+            MockServer::start_replying(vec![Reply::status(429).header("Retry-After", "5")]).await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            max_rate_limit_wait_secs: Some(1),
+            ..Default::default()
+        };
+
+        let result = dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await;
+
+        assert!(result.is_err_and(|e| matches!(e, Error::RateLimited)));
+        assert_eq!(server.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_all_falls_back_on_failure_test() -> Result<()> {
+        let primary = MockServer::start_replying(vec![Reply::status(500)]).await;
+        let fallback = MockServer::start().await;
+        let config = Config {
+            destination: vec![Destination {
+                fallback: Some(Box::new(webhook(&fallback.url))),
+                ..webhook(&primary.url)
+            }],
+            ..Default::default()
+        };
+
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+
+        assert_eq!(primary.requests().len(), 1);
+        let requests = fallback.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].body_text(), "hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_all_expands_env_in_url_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let url = OsString::from(&server.url);
+        let config = Config {
+            destination: vec![webhook("${HOOK_URL}/hook")],
+            env: Env::new(move |name| (name == "HOOK_URL").then(|| url.clone())),
+            ..Default::default()
+        };
+
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/hook");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_all_fails_fast_on_client_error_test() -> Result<()> {
+        let server = MockServer::start_replying(vec![Reply::status(400)]).await;
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            retry: Some(Retry {
+                max_attempts: 3,
+                initial_delay_ms: 1,
+            }),
+            ..Default::default()
+        };
+
+        let result = dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await;
+
+        assert!(result.is_err_and(|e| matches!(e, Error::Http(_))));
+        assert_eq!(server.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn get_and_delete_requests_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let destination = |method, url: String, template: &str| -> Destination {
+            DestinationKind::Webhook {
+                url,
+                title: None,
+                format: WebhookFormat::Custom(CustomWebhookFormat {
+                    http: Http {
+                        headers: IndexMap::new(),
+                        method,
+                    },
+                    template: template.into(),
+                    escape: true,
+                    auth: None,
+                    timeout_secs: None,
+                    response_log: None,
+                    gzip: false,
+                }),
+            }
+            .into()
+        };
+        let config = Config {
+            destination: vec![
+                destination(
+                    HttpMethod::GET,
+                    format!("{}/alert?source=noti", server.url),
+                    "text=$(message)",
+                ),
+                destination(
+                    HttpMethod::DELETE,
+                    server.url.clone(),
+                    r#"{"id":"$(message)"}"#,
+                ),
+            ],
+            ..Default::default()
+        };
+
+        dispatch_all(
+            r#"a "b"&c"#,
+            &Vars::new(),
+            None,
+            &config,
+            &Status::default(),
+        )
+        .await?;
+
+        let mut requests = server.requests();
+        requests.sort_by(|a, b| a.method.cmp(&b.method));
+        assert_eq!(requests[0].method, "DELETE");
+        assert_eq!(requests[0].body_text(), r#"{"id":"a \"b\"&c"}"#);
+        assert_eq!(requests[1].method, "GET");
+        assert_eq!(requests[1].path, "/alert?source=noti&text=a%20%22b%22%26c");
+        assert!(requests[1].body.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn head_request_logs_response_headers_test() -> Result<()> {
+        let green = Reply::status(200).header("X-Status", "green");
+        let server = MockServer::start_replying(vec![green.clone(), green]).await;
+        let config = Config {
+            destination: vec![DestinationKind::Webhook {
+                url: server.url.clone(),
+                title: None,
+                format: WebhookFormat::Custom(CustomWebhookFormat {
+                    http: Http {
+                        headers: IndexMap::new(),
+                        method: HttpMethod::HEAD,
+                    },
+                    template: "$(message)".into(),
+                    escape: false,
+                    auth: None,
+                    timeout_secs: None,
+                    response_log: Some("status: $(header:x-status)".into()),
+                    gzip: false,
+                }),
+            }
+            .into()],
+            ..Default::default()
+        };
+
+        dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await?;
+
+        let requests = server.requests();
+        assert_eq!(
+            reqwest::Method::from(HttpMethod::HEAD),
+            reqwest::Method::HEAD
+        );
+        assert_eq!(requests[0].method, "HEAD");
+        assert!(requests[0].body.is_empty());
+
+        let response = reqwest::Client::new().head(&server.url).send().await?;
+        let log = response_log("$(header:x-status) $(header:missing)", &response);
+        assert_eq!(log, "green $(header:missing)");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn exec_placeholder_in_webhook_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![DestinationKind::Webhook {
+                url: server.url.clone(),
+                title: None,
+                format: WebhookFormat::Custom(CustomWebhookFormat {
+                    http: Http {
+                        headers: IndexMap::new(),
+                        method: HttpMethod::POST,
+                    },
+                    template: "$(message) by $(exec:echo ci)".into(),
+                    escape: false,
+                    auth: None,
+                    timeout_secs: None,
+                    response_log: None,
+                    gzip: false,
+                }),
+            }
+            .into()],
+            allow_exec: Some(true),
+            ..Default::default()
+        };
+
+        dispatch_all("deployed", &Vars::new(), None, &config, &Status::default()).await?;
+
+        assert_eq!(server.requests()[0].body_text(), "deployed by ci");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn overflow_paste_appends_link_test() -> Result<()> {
+        let paste =
+            MockServer::start_replying(
+                vec![Reply::status(200).body("https://paste.example/abc\n")],
+            )
+            .await;
+        let server = MockServer::start().await;
+        let config = Config {
+            destination: vec![Destination {
+                max_length: Some(50),
+                overflow: Overflow::Paste {
+                    url: format!("{}/documents", paste.url),
+                },
+                ..webhook(&server.url)
+            }],
+            ..Default::default()
+        };
+        let long = format!("build failed\n{}", "trace line\n".repeat(10));
+
+        dispatch_all(&long, &Vars::new(), None, &config, &Status::default()).await?;
+
+        let uploads = paste.requests();
+        assert_eq!(uploads[0].path, "/documents");
+        assert_eq!(uploads[0].body_text(), long);
+        let sent = server.requests()[0].body_text();
+        assert_eq!(sent, "build failed\ntrace line…\nhttps://paste.example/abc");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn gzip_body_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let destination = |gzip| -> Destination {
+            DestinationKind::Webhook {
+                url: server.url.clone(),
+                title: None,
+                format: WebhookFormat::Custom(CustomWebhookFormat {
+                    http: Http {
+                        headers: IndexMap::new(),
+                        method: HttpMethod::POST,
+                    },
+                    template: "$(message)".into(),
+                    escape: false,
+                    auth: None,
+                    timeout_secs: None,
+                    response_log: None,
+                    gzip,
+                }),
+            }
+            .into()
+        };
+        for gzip in [true, false] {
+            let config = Config {
+                destination: vec![destination(gzip)],
+                ..Default::default()
+            };
+            dispatch_all("a\nb", &Vars::new(), None, &config, &Status::default()).await?;
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("content-encoding"), Some("gzip"));
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(requests[0].body.as_slice()).read_to_string(&mut body)?;
+        assert_eq!(body, "a\nb");
+        assert_eq!(requests[1].header("content-encoding"), None);
+        assert_eq!(requests[1].body_text(), "a\nb");
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_email_test() -> Result<()> {
+        let server = MockSmtpServer::start().await;
+        let config = Config {
+            destination: vec![DestinationKind::Email(Email {
+                smtp_host: "127.0.0.1".into(),
+                smtp_port: server.port,
+                from: "noti <noti@example.com>".into(),
+                to: "you@example.com".into(),
+                subject: "Nightly job".into(),
+                tls: SmtpTls::None,
+                username_env: None,
+                password_env: None,
+            })
+            .into()],
+            ..Default::default()
+        };
+
+        dispatch_all(
+            "backup complete",
+            &Vars::new(),
+            None,
+            &config,
+            &Status::default(),
+        )
+        .await?;
+
+        let emails = server.emails();
+        assert_eq!(emails.len(), 1);
+        assert!(emails[0].contains("Subject: Nightly job"));
+        assert!(emails[0].contains("To: you@example.com"));
+        assert!(emails[0].ends_with("backup complete"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_all_tries_every_destination_test() -> Result<()> {
+        let broken = MockServer::start_replying(vec![Reply::status(404), Reply::status(404)]).await;
+        let working = MockServer::start().await;
+        let config = Config {
+            destination: vec![
+                webhook(&broken.url),
+                webhook(&working.url),
+                Destination {
+                    name: Some("oncall".into()),
+                    ..webhook(&broken.url)
+                },
+            ],
+            ..Default::default()
+        };
+
+        let result = dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await;
+
+        assert_eq!(working.requests().len(), 1);
+        let Err(Error::DestinationsFailed { failures }) = result else {
+            panic!("expected both broken destinations to fail, got {result:?}");
+        };
+        let names: Vec<&str> = failures.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["webhook #1", "oncall"]);
+
+        let broken = MockServer::start_replying(vec![Reply::status(404)]).await;
+        let config = Config {
+            destination: vec![webhook(&broken.url), webhook(&working.url)],
+            max_concurrency: Some(1),
+            fail_fast: Some(true),
+            ..Default::default()
+        };
+        let result = dispatch_all("hello", &Vars::new(), None, &config, &Status::default()).await;
+
+        assert!(result.is_err_and(|e| matches!(e, Error::Http(_))));
+        assert_eq!(working.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn throttle_sends_once_across_invocations_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let dir = TempDir::new("throttle_sends_once_across_invocations_test")?;
+        let state_dir = dir.join("state");
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            throttle: Some(Throttle {
+                window_secs: 60,
+                state_dir: Some(state_dir.clone()),
+            }),
+            ..Default::default()
+        };
+
+        // Each invocation gets its own thread and runtime, like separate processes.
+        let results: Vec<Result<()>> = std::thread::scope(|scope| {
+            let invocations: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        tokio::runtime::Builder::new_current_thread()
+                            .enable_all()
+                            .build()?
+                            .block_on(dispatch_all(
+                                "disk full",
+                                &Vars::new(),
+                                None,
+                                &config,
+                                &Status::default(),
+                            ))
+                    })
+                })
+                .collect();
+            invocations.into_iter().map(|i| i.join().unwrap()).collect()
+        });
+        for result in results {
+            result?;
+        }
+        assert_eq!(server.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    pub async fn throttle_resends_after_failure_test() -> Result<()> {
+        let server = MockServer::start_replying(vec![Reply::status(500)]).await;
+        let dir = TempDir::new("throttle_resends_after_failure_test")?;
+        let state_dir = dir.join("state");
+        let config = Config {
+            destination: vec![webhook(&server.url)],
+            throttle: Some(Throttle {
+                window_secs: 60,
+                state_dir: Some(state_dir.clone()),
+            }),
+            ..Default::default()
+        };
+
+        let first =
+            dispatch_all("disk full", &Vars::new(), None, &config, &Status::default()).await;
+        let second =
+            dispatch_all("disk full", &Vars::new(), None, &config, &Status::default()).await;
+        let third =
+            dispatch_all("disk full", &Vars::new(), None, &config, &Status::default()).await;
+
+        assert!(first.is_err());
+        assert!(second.is_ok() && third.is_ok());
+        assert_eq!(server.requests().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    pub async fn crash_hook_notifies_crash_destination_test() -> Result<()> {
+        let server = MockServer::start().await;
+        install_crash_hook(webhook(&server.url), RequestOptions::default());
+
+        let result = std::panic::catch_unwind(|| panic!("crash hook test"));
+        let _ = std::panic::take_hook();
+
+        assert!(result.is_err());
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].body_text().starts_with("noti crashed"));
+        assert!(requests[0].body_text().contains("crash hook test"));
+        Ok(())
+    }
+
+    #[test]
+    pub fn notify_crash_makes_one_attempt_test() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let failing = rt.block_on(MockServer::start_replying(vec![Reply::status(500); 3]));
+        let fallback = rt.block_on(MockServer::start());
+        let destination = Destination {
+            fallback: Some(Box::new(webhook(&fallback.url))),
+            ..webhook(&failing.url)
+        };
+        let options = RequestOptions {
+            retry: Some(Retry {
+                max_attempts: 3,
+                initial_delay_ms: 1,
+            }),
+            ..Default::default()
+        };
+
+        notify_crash("noti crashed", &destination, &options);
+
+        assert_eq!(failing.requests().len(), 1);
+        assert!(fallback.requests().is_empty());
+    }
+
+    #[cfg(feature = "integration_tests")]
+    #[tokio::test]
+    pub async fn init_with_test_attempts_dispatch_test() -> Result<()> {
+        let dir = TempDir::new("init_with_test_attempts_dispatch_test")?;
+        let temp_cfg = dir.join("noti.yaml");
+
+        // The generated config points at a placeholder URL, so the test dispatch fails.
+        let res = init(&temp_cfg, &DestinationType::Webhook, false, true).await;
+        assert!(res.is_err_and(|e| matches!(e, Error::TestFailed { failed: 1 })));
+        assert!(tokio::fs::try_exists(&temp_cfg).await?);
         Ok(())
     }
 
@@ -219,8 +4455,11 @@ mod test {
 
         dispatch_webhook(
             MESSAGE,
+            &Vars::new(),
             url.as_str(),
             &WebhookFormat::Standard(StandardWebhookFormat::Discord),
+            None,
+            &RequestOptions::default(),
         )
         .await?;
 
@@ -235,8 +4474,11 @@ mod test {
 
         dispatch_webhook(
             MESSAGE,
+            &Vars::new(),
             url.as_str(),
             &WebhookFormat::Standard(StandardWebhookFormat::GoogleChat),
+            None,
+            &RequestOptions::default(),
         )
         .await?;
 
@@ -251,8 +4493,11 @@ mod test {
 
         dispatch_webhook(
             MESSAGE,
+            &Vars::new(),
             url.as_str(),
             &WebhookFormat::Standard(StandardWebhookFormat::PlainText),
+            None,
+            &RequestOptions::default(),
         )
         .await?;
 
@@ -267,6 +4512,7 @@ mod test {
 
         dispatch_webhook(
             MESSAGE,
+            &Vars::new(),
             url.as_str(),
             &WebhookFormat::Custom(CustomWebhookFormat {
                 http: Http {
@@ -275,7 +4521,13 @@ mod test {
                 },
                 template: r#"{"message":"$(message)"}"#.into(),
                 escape: true,
+                auth: None,
+                timeout_secs: None,
+                response_log: None,
+                gzip: false,
             }),
+            None,
+            &RequestOptions::default(),
         )
         .await?;
 
@@ -0,0 +1,176 @@
+//! Desktop notifications sent with the freedesktop `Notify` D-Bus method, for the
+//! replacement ids and raw hints which `notify_rust` doesn't expose.
+use crate::{config::HintValue, error::Result};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use zbus::{zvariant::Value, Connection};
+
+const DESTINATION: &str = "org.freedesktop.Notifications";
+const PATH: &str = "/org/freedesktop/Notifications";
+const INTERFACE: &str = "org.freedesktop.Notifications";
+const METHOD: &str = "Notify";
+
+/// Urgency hint of notifications about a command which failed.
+const CRITICAL: u8 = 2;
+
+/// Arguments of `Notify`: app name, replaces id, icon, summary, body, actions,
+/// hints and expiry timeout.
+type NotifyArgs<'a> = (
+    &'a str,
+    u32,
+    &'a str,
+    &'a str,
+    &'a str,
+    Vec<&'a str>,
+    HashMap<&'a str, Value<'a>>,
+    i32,
+);
+
+/// A notification to show on the desktop.
+pub struct Notification<'a> {
+    pub summary: &'a str,
+    pub body: &'a str,
+    pub icon: Option<&'a str>,
+    pub persistent: bool,
+    pub failed: bool,
+    /// Percentage shown with the `value` hint.
+    pub progress: Option<i32>,
+    /// Id of a notification to replace, rather than showing a new one.
+    pub replaces_id: Option<u32>,
+    /// Hints sent as they are, overriding the urgency and progress hints.
+    pub hints: &'a IndexMap<String, HintValue>,
+}
+
+impl Notification<'_> {
+    fn args(&self) -> NotifyArgs<'_> {
+        let mut hints = HashMap::new();
+        if self.failed {
+            hints.insert("urgency", Value::U8(CRITICAL));
+        }
+        if let Some(progress) = self.progress {
+            hints.insert("value", Value::I32(progress));
+        }
+        for (name, value) in self.hints {
+            let value = match value {
+                HintValue::Bool(value) => Value::Bool(*value),
+                HintValue::Int(value) => Value::I32(*value),
+                HintValue::String(value) => Value::from(value.as_str()),
+            };
+            hints.insert(name.as_str(), value);
+        }
+
+        (
+            "noti",
+            self.replaces_id.unwrap_or(0),
+            self.icon.unwrap_or_default(),
+            self.summary,
+            self.body,
+            vec![],
+            hints,
+            if self.persistent { 0 } else { -1 },
+        )
+    }
+}
+
+/// Show `notification` over the session bus, returning the id the server gave it.
+pub async fn dispatch(notification: &Notification<'_>) -> Result<u32> {
+    let connection = Connection::session().await?;
+    let reply = connection
+        .call_method(
+            Some(DESTINATION),
+            PATH,
+            Some(INTERFACE),
+            METHOD,
+            &notification.args(),
+        )
+        .await?;
+    Ok(reply.body().deserialize()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Notification, DESTINATION, INTERFACE, METHOD, PATH};
+    use crate::{config::HintValue, error::Result};
+    use indexmap::IndexMap;
+    use std::collections::HashMap;
+    use zbus::{message::Message, zvariant::OwnedValue};
+
+    type Args = (
+        String,
+        u32,
+        String,
+        String,
+        String,
+        Vec<String>,
+        HashMap<String, OwnedValue>,
+        i32,
+    );
+
+    #[test]
+    pub fn notify_call_arguments_test() -> Result<()> {
+        let hints = IndexMap::from([
+            ("category".to_string(), HintValue::String("transfer".into())),
+            ("resident".to_string(), HintValue::Bool(true)),
+            ("value".to_string(), HintValue::Int(75)),
+        ]);
+        let notification = Notification {
+            summary: "Backup",
+            body: "Copied 3 of 4 files",
+            icon: Some("drive-harddisk"),
+            persistent: true,
+            failed: true,
+            progress: Some(50),
+            replaces_id: Some(42),
+            hints: &hints,
+        };
+
+        let message = Message::method_call(PATH, METHOD)?
+            .destination(DESTINATION)?
+            .interface(INTERFACE)?
+            .build(&notification.args())?;
+        let header = message.header();
+        assert_eq!(header.member().map(|m| m.as_str()), Some("Notify"));
+        assert_eq!(
+            header.interface().map(|i| i.as_str()),
+            Some("org.freedesktop.Notifications")
+        );
+
+        let (app, replaces_id, icon, summary, body, actions, hints, timeout): Args =
+            message.body().deserialize()?;
+        assert_eq!(app, "noti");
+        assert_eq!(replaces_id, 42);
+        assert_eq!(icon, "drive-harddisk");
+        assert_eq!(
+            (summary.as_str(), body.as_str()),
+            ("Backup", "Copied 3 of 4 files")
+        );
+        assert!(actions.is_empty());
+        assert_eq!(timeout, 0);
+
+        assert_eq!(hints.len(), 4);
+        assert_eq!(u8::try_from(&hints["urgency"]).ok(), Some(2));
+        assert_eq!(<&str>::try_from(&hints["category"]).ok(), Some("transfer"));
+        assert_eq!(bool::try_from(&hints["resident"]).ok(), Some(true));
+        assert_eq!(i32::try_from(&hints["value"]).ok(), Some(75));
+        Ok(())
+    }
+
+    #[test]
+    pub fn notify_call_defaults_test() {
+        let hints = IndexMap::new();
+        let notification = Notification {
+            summary: "Noti",
+            body: "done",
+            icon: None,
+            persistent: false,
+            failed: false,
+            progress: None,
+            replaces_id: None,
+            hints: &hints,
+        };
+
+        let (_, replaces_id, icon, _, _, _, hints, timeout) = notification.args();
+        assert_eq!((replaces_id, icon, timeout), (0, "", -1));
+        assert!(hints.is_empty());
+    }
+}
@@ -1,5 +1,9 @@
 use derive_more::{Error, From};
-use std::path::PathBuf;
+use regex::{Captures, Regex};
+use std::{
+    path::PathBuf,
+    sync::{LazyLock, OnceLock},
+};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -7,16 +11,93 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     NoConfig,
     NoMessage,
+    NoDestinations,
     StreamAndMessage,
+    StdinAndMessage,
     Io(std::io::Error),
+    MessageFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    CaCert {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    SampleFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    FollowFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
     ConfigConflict {
         path: PathBuf,
     },
+    TestFailed {
+        failed: usize,
+    },
+    ValidationFailed {
+        failed: usize,
+    },
+    UnknownPreset {
+        name: String,
+        path: PathBuf,
+    },
+    MissingEnvVar {
+        name: String,
+    },
+    UnknownDestination {
+        name: String,
+        known: Vec<String>,
+    },
+    UnknownConfigKeys {
+        keys: Vec<String>,
+    },
+    /// Several destinations failed, each referred to by name or like `webhook #1`.
+    DestinationsFailed {
+        failures: Vec<(String, Error)>,
+    },
 
     #[from]
     InvalidConfig(serde_yaml::Error),
 
+    #[from]
+    InvalidTomlConfig(toml::de::Error),
+
+    #[from]
+    TomlSerialize(toml::ser::Error),
+
+    #[cfg(feature = "grpc")]
+    InvalidGrpcMethod {
+        service: String,
+        method: String,
+    },
+
+    #[cfg(feature = "grpc")]
+    #[from]
+    GrpcTransport(tonic::transport::Error),
+
+    #[cfg(feature = "grpc")]
+    GrpcStatus(Box<tonic::Status>),
+
+    #[cfg(feature = "kafka")]
+    #[from]
+    Kafka(rdkafka::error::KafkaError),
+
+    #[cfg(feature = "kafka")]
+    KafkaDelivery(rdkafka::error::KafkaError),
+
     Http(reqwest::Error),
+    Timeout,
+    RateLimited,
+    Redirected {
+        status: u16,
+    },
+    ExecFailed {
+        command: String,
+        code: Option<i32>,
+    },
 
     #[from]
     UnknownHttpHeader(reqwest::header::InvalidHeaderName),
@@ -27,36 +108,202 @@ pub enum Error {
     #[from]
     Regex(regex::Error),
 
+    #[from]
+    InvalidUrl(url::ParseError),
+
+    #[from]
+    InvalidEmailAddress(lettre::address::AddressError),
+
+    #[from]
+    Email(lettre::error::Error),
+
+    #[from]
+    Smtp(lettre::transport::smtp::Error),
+
     #[from]
     NotifyRust(notify_rust::error::Error),
+
+    #[cfg(feature = "dbus")]
+    #[from]
+    Dbus(zbus::Error),
+}
+
+impl Error {
+    /// The HTTP status of the response which caused this error, if any.
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            Self::Http(e) => e.status().map(|status| status.as_u16()),
+            Self::Redirected { status } => Some(*status),
+            Self::RateLimited => Some(429),
+            _ => None,
+        }
+    }
+}
+
+/// How much of an error to show when it is displayed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorDetail {
+    /// Only say what went wrong, in one line.
+    #[default]
+    Short,
+    /// Also include the chain of errors which caused it.
+    Full,
+}
+
+static ERROR_DETAIL: OnceLock<ErrorDetail> = OnceLock::new();
+
+/// Set how errors are displayed for the rest of the program.
+///
+/// Errors formatted with `{:#}` always include their full detail.
+pub fn set_detail(detail: ErrorDetail) {
+    let _ = ERROR_DETAIL.set(detail);
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let message = match self {
+        let full = f.alternate() || ERROR_DETAIL.get() == Some(&ErrorDetail::Full);
+        let mut message = match self {
             Self::NoConfig => {
                 "No config file found, please create one or provide the path with --config".into()
             }
+            Self::MessageFile { path, .. } => {
+                format!("Failed to read message file `{}`", path.to_string_lossy())
+            }
+            Self::CaCert { path, .. } => {
+                format!("Failed to read CA certificate `{}`", path.to_string_lossy())
+            }
+            Self::SampleFile { path, .. } => {
+                format!("Failed to read sample file `{}`", path.to_string_lossy())
+            }
+            Self::FollowFile { path, .. } => {
+                format!("Failed to open followed file `{}`", path.to_string_lossy())
+            }
             Self::ConfigConflict { path } => {
                 format!("Config file `{}` already exists", path.to_string_lossy())
             }
-            Self::InvalidConfig(e) => format!("Invalid config file: {e}"),
-            Self::Http(e) => format!("An error occurred when sending a request: {e}"),
-            Self::UnknownHttpHeader(e) => format!("{e}"),
-            Self::InvalidHttpHeader(e) => format!("{e}"),
-            Self::Regex(e) => format!("Failed to parse regex: {e}"),
-            Self::Io(e) => format!("IO: {e}"),
+            Self::TestFailed { failed } => {
+                format!("{failed} destination(s) failed to receive a test notification")
+            }
+            Self::ValidationFailed { failed } => {
+                format!("Found {failed} problem(s) in the config")
+            }
+            Self::UnknownPreset { name, path } => format!(
+                "Webhook format preset `{name}` not found at `{}`",
+                path.to_string_lossy()
+            ),
+            Self::UnknownConfigKeys { keys } => {
+                format!("Unknown config key(s): {}", keys.join(", "))
+            }
+            Self::DestinationsFailed { failures } => {
+                let failures: Vec<String> = failures
+                    .iter()
+                    .map(|(destination, e)| match full {
+                        true => format!("{destination}: {e:#}"),
+                        false => format!("{destination}: {e}"),
+                    })
+                    .collect();
+                format!(
+                    "{} destinations failed: {}",
+                    failures.len(),
+                    failures.join("; ")
+                )
+            }
+            Self::MissingEnvVar { name } => {
+                format!("Environment variable `{name}` is not set")
+            }
+            Self::UnknownDestination { name, known } => match known.is_empty() {
+                true => format!("No destination is named `{name}`, as none have a `name`"),
+                false => format!(
+                    "No destination is named `{name}`, expected one of: {}",
+                    known.join(", ")
+                ),
+            },
+            Self::InvalidConfig(_) => "Invalid config file".into(),
+            Self::InvalidTomlConfig(_) => "Invalid config file".into(),
+            Self::TomlSerialize(_) => "Failed to write config as TOML".into(),
+            #[cfg(feature = "grpc")]
+            Self::InvalidGrpcMethod { service, method } => {
+                format!("`/{service}/{method}` is not a valid gRPC method")
+            }
+            #[cfg(feature = "grpc")]
+            Self::GrpcTransport(_) => "Failed to connect to gRPC server".into(),
+            #[cfg(feature = "grpc")]
+            Self::GrpcStatus(_) => "gRPC call failed".into(),
+            #[cfg(feature = "kafka")]
+            Self::Kafka(_) => "Failed to create Kafka producer".into(),
+            #[cfg(feature = "kafka")]
+            Self::KafkaDelivery(_) => "Failed to deliver message to Kafka".into(),
+            Self::Http(_) => "An error occurred when sending a request".into(),
+            Self::Timeout => "Timed out waiting for a response".into(),
+            Self::RateLimited => "The destination is rate limiting requests".into(),
+            Self::ExecFailed { command, code } => match code {
+                Some(code) => format!("Command `{command}` failed with exit code {code}"),
+                None => format!("Command `{command}` was killed by a signal"),
+            },
+            Self::Redirected { status } => format!(
+                "The destination redirected with status {status}, and `follow_redirects` is off"
+            ),
+            Self::UnknownHttpHeader(_) => "Invalid HTTP header name".into(),
+            Self::InvalidHttpHeader(_) => "Invalid HTTP header value".into(),
+            Self::Regex(_) => "Failed to parse regex".into(),
+            Self::InvalidUrl(_) => "Invalid URL".into(),
+            Self::InvalidEmailAddress(_) => "Invalid email address".into(),
+            Self::Email(_) => "Failed to build email".into(),
+            Self::Smtp(_) => "Failed to send email".into(),
+            Self::Io(e) => format!("IO: {}", e.kind()),
             Error::NoMessage => {
                 "A message must be provided when not streaming notifications".into()
             }
+            Error::NoDestinations => "The config has no destinations to send to".into(),
             Error::StreamAndMessage => "A message cannot be provided when using streaming".into(),
-            Error::NotifyRust(e) => format!("Failed to send desktop notification: {e}"),
+            Error::StdinAndMessage => {
+                "A message cannot be provided when reading it from stdin".into()
+            }
+            Error::NotifyRust(_) => "Failed to send desktop notification".into(),
+            #[cfg(feature = "dbus")]
+            Error::Dbus(_) => "Failed to send desktop notification over D-Bus".into(),
         };
 
-        write!(f, "{message}")
+        if full {
+            let mut source = std::error::Error::source(self);
+            while let Some(e) = source {
+                message.push_str(&format!(": {e}"));
+                source = e.source();
+            }
+        }
+
+        write!(f, "{}", redact(&message))
     }
 }
 
+/// Credentials given with an auth scheme, like `Bearer <token>`, or in a
+/// Telegram bot URL.
+static AUTH_CREDENTIALS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(bearer|basic)(\s+)[A-Za-z0-9._~+/=-]+|\b(bot)()[0-9]+:[A-Za-z0-9_-]+")
+        .unwrap()
+});
+
+/// Words long enough to be tokens or IDs, like Discord webhook IDs and tokens,
+/// or Telegram bot tokens.
+static TOKEN_LIKE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b[0-9]{16,}\b|[A-Za-z0-9_:-]{24,}").unwrap());
+
+/// Mask anything in `message` which looks like a secret, so errors can be shared safely.
+fn redact(message: &str) -> String {
+    let message = AUTH_CREDENTIALS.replace_all(message, "$1$2$3$4***");
+    TOKEN_LIKE
+        .replace_all(&message, |captures: &Captures| {
+            let word = &captures[0];
+            let mixed = word.bytes().any(|b| b.is_ascii_digit())
+                && word.bytes().any(|b| b.is_ascii_alphabetic());
+            match mixed || word.bytes().all(|b| b.is_ascii_digit()) {
+                true => "***".to_string(),
+                false => word.to_string(),
+            }
+        })
+        .into_owned()
+}
+
 impl std::convert::From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
         match error.kind() {
@@ -68,7 +315,71 @@ impl std::convert::From<std::io::Error> for Error {
 
 impl std::convert::From<reqwest::Error> for Error {
     fn from(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            return Self::Timeout;
+        }
         let error = error.without_url();
         Self::Http(error)
     }
 }
+
+#[cfg(feature = "grpc")]
+impl std::convert::From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Self::GrpcStatus(Box::new(status))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{redact, Error};
+
+    #[test]
+    pub fn error_detail_test() {
+        let source = serde_yaml::from_str::<serde_yaml::Value>("a: [").unwrap_err();
+        let detail = source.to_string();
+        let error = Error::InvalidConfig(source);
+
+        assert_eq!(error.to_string(), "Invalid config file");
+        assert_eq!(
+            format!("{error:#}"),
+            format!("Invalid config file: {detail}")
+        );
+
+        let error = Error::DestinationsFailed {
+            failures: vec![
+                ("webhook #1".into(), error),
+                ("desktop #2".into(), Error::Timeout),
+            ],
+        };
+        assert!(!error.to_string().contains(&detail));
+        assert!(format!("{error:#}").contains(&detail));
+    }
+
+    #[test]
+    pub fn display_redacts_secrets_test() {
+        assert_eq!(
+            redact(
+                "https://discord.com/api/webhooks/1234567890123456789/\
+                 aBcD3fGhIjKlMnOpQrStUvWxYz0123456789 failed"
+            ),
+            "https://discord.com/api/webhooks/***/*** failed"
+        );
+        assert_eq!(
+            redact("sent Authorization: Bearer s3cr3t to https://api.telegram.org/bot123:ABC"),
+            "sent Authorization: Bearer *** to https://api.telegram.org/bot***"
+        );
+        // Ordinary words and config paths are left alone.
+        assert_eq!(
+            redact("`resolve_named_preset_test_presets/format.yaml` not found at port 8080"),
+            "`resolve_named_preset_test_presets/format.yaml` not found at port 8080"
+        );
+        assert_eq!(
+            Error::MissingEnvVar {
+                name: "Bearer abc".into()
+            }
+            .to_string(),
+            "Environment variable `Bearer ***` is not set"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Sending notifications to gRPC services, using a fixed protobuf schema.
+use crate::error::{Error, Result};
+use tonic::{codec::ProstCodec, codegen::http::uri::PathAndQuery, transport::Endpoint};
+
+/// Request sent to the notification RPC, equivalent to:
+///
+/// ```proto
+/// message NotifyRequest {
+///   string message = 1;
+/// }
+/// ```
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct NotifyRequest {
+    #[prost(string, tag = "1")]
+    pub message: String,
+}
+
+/// Response from the notification RPC, whose fields are ignored.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct NotifyResponse {}
+
+/// Call `method` of `service` on the gRPC server at `endpoint` with the message.
+pub async fn dispatch(message: &str, endpoint: &str, service: &str, method: &str) -> Result<()> {
+    let path = PathAndQuery::try_from(format!("/{service}/{method}")).map_err(|_| {
+        Error::InvalidGrpcMethod {
+            service: service.into(),
+            method: method.into(),
+        }
+    })?;
+
+    let channel = Endpoint::from_shared(endpoint.to_string())?
+        .connect()
+        .await?;
+    let mut client = tonic::client::Grpc::new(channel);
+    client.ready().await?;
+
+    let request = tonic::Request::new(NotifyRequest {
+        message: message.into(),
+    });
+    client
+        .unary::<_, NotifyResponse, _>(request, path, ProstCodec::default())
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dispatch, NotifyRequest, NotifyResponse};
+    use crate::error::Result;
+    use std::{
+        convert::Infallible,
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+    };
+    use tonic::{
+        body::BoxBody,
+        codec::ProstCodec,
+        codegen::{http, Service},
+        server::{Grpc, NamedService, UnaryService},
+        transport::Server,
+    };
+
+    type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+    /// Notifier service recording every message it is sent.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl UnaryService<NotifyRequest> for Recorder {
+        type Response = NotifyResponse;
+        type Future =
+            BoxFuture<std::result::Result<tonic::Response<NotifyResponse>, tonic::Status>>;
+
+        fn call(&mut self, request: tonic::Request<NotifyRequest>) -> Self::Future {
+            self.0.lock().unwrap().push(request.into_inner().message);
+            Box::pin(async { Ok(tonic::Response::new(NotifyResponse {})) })
+        }
+    }
+
+    impl<B> Service<http::Request<B>> for Recorder
+    where
+        B: tonic::codegen::Body + Send + 'static,
+        B::Error: Into<tonic::codegen::StdError> + Send + 'static,
+    {
+        type Response = http::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = BoxFuture<std::result::Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: http::Request<B>) -> Self::Future {
+            let recorder = self.clone();
+            Box::pin(async move {
+                Ok(Grpc::new(ProstCodec::default())
+                    .unary(recorder, request)
+                    .await)
+            })
+        }
+    }
+
+    impl NamedService for Recorder {
+        const NAME: &'static str = "noti.Notifier";
+    }
+
+    #[tokio::test]
+    pub async fn dispatch_grpc_invokes_rpc_test() -> Result<()> {
+        let recorder = Recorder::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        let incoming = futures::stream::unfold(listener, |listener| async move {
+            Some((listener.accept().await.map(|(stream, _)| stream), listener))
+        });
+        tokio::spawn(
+            Server::builder()
+                .add_service(recorder.clone())
+                .serve_with_incoming(incoming),
+        );
+
+        dispatch("hello", &endpoint, "noti.Notifier", "Notify").await?;
+
+        assert_eq!(*recorder.0.lock().unwrap(), ["hello"]);
+        Ok(())
+    }
+}
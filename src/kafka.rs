@@ -0,0 +1,84 @@
+//! Producing notifications to Kafka topics.
+use crate::error::{Error, Result};
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord},
+    util::Timeout,
+    ClientConfig,
+};
+use std::time::Duration;
+
+/// How long to wait for a message to be delivered when no timeout is configured.
+const DEFAULT_DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Produce the message to `topic` on the cluster at `brokers`, with an optional `key`.
+///
+/// Returns once the message has been acknowledged, or has failed to be
+/// delivered within `timeout`.
+pub async fn dispatch(
+    message: &str,
+    brokers: &str,
+    topic: &str,
+    key: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let timeout = timeout.unwrap_or(DEFAULT_DELIVERY_TIMEOUT);
+    let producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("message.timeout.ms", timeout.as_millis().to_string())
+        .create()?;
+
+    let mut record = FutureRecord::<str, str>::to(topic).payload(message);
+    if let Some(key) = key {
+        record = record.key(key);
+    }
+    producer
+        .send(record, Timeout::Never)
+        .await
+        .map_err(|(e, _)| Error::KafkaDelivery(e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::dispatch;
+    use crate::error::Result;
+    use rdkafka::{
+        consumer::{BaseConsumer, Consumer},
+        mocking::MockCluster,
+        ClientConfig, Message, Offset, TopicPartitionList,
+    };
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    pub async fn dispatch_produces_to_topic_test() -> Result<()> {
+        let cluster = MockCluster::new(1)?;
+        cluster.create_topic("alerts", 1, 1)?;
+        let brokers = cluster.bootstrap_servers();
+
+        dispatch("disk full", &brokers, "alerts", Some("db-1"), None).await?;
+
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", "dispatch_produces_to_topic_test")
+            .create()?;
+        let mut partitions = TopicPartitionList::new();
+        partitions.add_partition_offset("alerts", 0, Offset::Beginning)?;
+        consumer.assign(&partitions)?;
+
+        let start = Instant::now();
+        let message = loop {
+            if let Some(message) = consumer.poll(Duration::from_millis(100)) {
+                break message?.detach();
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "no message produced"
+            );
+        };
+
+        assert_eq!(message.payload(), Some("disk full".as_bytes()));
+        assert_eq!(message.key(), Some("db-1".as_bytes()));
+        Ok(())
+    }
+}
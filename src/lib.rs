@@ -0,0 +1,40 @@
+//! noti as a library, for sending notifications from other Rust programs without
+//! running the `noti` command.
+//!
+//! A [`Notifier`] sends each message to every destination in a [`Config`], with the
+//! same retries, fallbacks and rate limiting as the command.
+//!
+//! ```no_run
+//! use noti::{Config, Notifier};
+//!
+//! # async fn send() -> noti::Result<()> {
+//! let notifier = Notifier::new(Config::default_desktop());
+//! notifier.notify("Backup complete").await?;
+//! # Ok(())
+//! # }
+//! ```
+#[deny(unsafe_code)]
+#[doc(hidden)]
+pub mod cli;
+#[doc(hidden)]
+pub mod commands;
+pub mod config;
+#[cfg(feature = "dbus")]
+mod dbus;
+pub mod error;
+#[cfg(feature = "grpc")]
+mod grpc;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(test)]
+mod mock;
+mod notifier;
+pub mod status;
+pub mod template;
+mod throttle;
+
+pub use crate::{
+    config::{Config, Destination, DestinationKind},
+    error::{Error, Result},
+    notifier::Notifier,
+};
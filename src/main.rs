@@ -28,36 +28,99 @@
 //! ```
 //!
 //! ```sh
-//! # Long running task 
+//! # Long running task
 //! dbt run --target ... | noti
 //! ```
 //!
 //! The above will only send notifications for inputs that start with either `WARN:`
 //! or `ERROR:`.
-#[deny(unsafe_code)]
-mod cli;
-mod commands;
-mod config;
-mod error;
-use crate::{
-    cli::{Cli, Command},
-    error::Result,
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches};
+use noti::{
+    cli::{Cli, Command, Output},
+    commands,
+    config::Config,
+    error::{self, Result},
+    status::Status,
 };
-use clap::Parser;
+use std::{io::IsTerminal, sync::Arc};
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Log to stderr at a level set by how many times `--verbose` is given.
+///
+/// Logs from dependencies are only shown from `-vvv` on.
+fn init_logging(verbose: u8) {
+    let level = match verbose {
+        0 => return,
+        1 => tracing::Level::INFO,
+        2 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    let targets = match verbose {
+        3.. => Targets::new().with_default(level),
+        _ => Targets::new().with_target("noti", level),
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_ansi(std::io::stderr().is_terminal()),
+        )
+        .with(targets)
+        .init();
+}
 
 #[tokio::main]
 async fn main() {
-    let args = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if matches.value_source("config") == Some(ValueSource::DefaultValue) {
+        args.config = vec![Config::default_path(args.config_file())];
+    }
+    init_logging(args.verbose);
+    error::set_detail(args.error_detail);
+    let desktop_on_invalid_config = args.desktop_on_invalid_config;
+    let config_file = args.config_file().clone();
+    let output = args.output;
+    let status = Arc::new(Status::default());
 
     let result: Result<()> = match args.command {
         Some(cmd) => match cmd {
-            Command::Init { destination, custom } => commands::init(&args.config, &destination, custom).await,
-            Command::Destination { command } => commands::destination(&args.config, &command).await,
+            Command::Init {
+                destination,
+                custom,
+                test,
+            } => commands::init(&config_file, &destination, custom, test).await,
+            Command::Test => commands::test(&args.config).await,
+            Command::Validate => commands::validate(&args.config, args.strict),
+            Command::Preview { sample } => commands::preview(&args.config, &sample).await,
+            Command::Version { json } => commands::version(json).await,
+            Command::Completions { shell } => commands::completions(shell, &mut std::io::stdout()),
+            Command::Run { command } => {
+                commands::run(
+                    &args.config,
+                    &args.to,
+                    &command,
+                    args.priority,
+                    args.severity,
+                    &status,
+                )
+                .await
+            }
+            Command::Destination { command } => commands::destination(&config_file, &command).await,
         },
-        None => commands::execute(args).await,
+        None => commands::execute(args, status.clone()).await,
     };
 
+    if output == Output::Json {
+        println!("{}", status.report(&result));
+    }
     if let Err(err) = result {
-        println!("ERROR: {err}")
+        if output == Output::Text {
+            println!("ERROR: {err}");
+        }
+        if desktop_on_invalid_config {
+            commands::notify_invalid_config(&err);
+        }
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,104 @@
+//! Sending notifications from other programs, through the same dispatch as the
+//! `noti` command.
+use crate::{
+    commands,
+    config::{Config, Priority},
+    error::Result,
+    status::Status,
+    template::Vars,
+};
+use std::path::PathBuf;
+
+/// Sends messages to every destination in a [`Config`].
+#[derive(Debug)]
+pub struct Notifier {
+    config: Config,
+    status: Status,
+}
+
+impl Notifier {
+    /// A notifier sending to the destinations in `config`.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            status: Status::default(),
+        }
+    }
+
+    /// A notifier sending to the destinations of the configs at `paths`, merged in
+    /// order as the `--config` option does.
+    pub fn load(paths: &[PathBuf]) -> Result<Self> {
+        Ok(Self::new(Config::load_all(paths, false)?))
+    }
+
+    /// The config messages are sent with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Counters of what has been sent so far.
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// Send `message` to every destination.
+    ///
+    /// Every destination is tried even if others fail, unless `fail_fast` is set.
+    pub async fn notify(&self, message: &str) -> Result<()> {
+        self.notify_with(message, None, &Vars::new()).await
+    }
+
+    /// Send `message` with a `priority`, filling `$(name)` placeholders in
+    /// templates from `vars`.
+    pub async fn notify_with(
+        &self,
+        message: &str,
+        priority: Option<Priority>,
+        vars: &Vars,
+    ) -> Result<()> {
+        commands::dispatch_all(message, vars, priority, &self.config, &self.status).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Notifier;
+    use crate::{
+        config::{Config, Destination, DestinationKind, StandardWebhookFormat, WebhookFormat},
+        error::Result,
+        mock::MockServer,
+        template::Vars,
+    };
+
+    #[tokio::test]
+    pub async fn notifier_sends_to_every_destination_test() -> Result<()> {
+        let server = MockServer::start().await;
+        let webhook = Destination::from(DestinationKind::Webhook {
+            url: format!("{}/$(service)", server.url),
+            format: WebhookFormat::Standard(StandardWebhookFormat::PlainText),
+            title: None,
+        });
+        let notifier = Notifier::new(Config {
+            destination: vec![webhook],
+            ..Default::default()
+        });
+
+        notifier.notify("deployed").await?;
+        let vars = Vars::from([("service".to_string(), "api".to_string())]);
+        notifier.notify_with("rolled back", None, &vars).await?;
+
+        let requests = server.requests();
+        let sent: Vec<_> = requests
+            .iter()
+            .map(|r| (r.path.as_str(), r.body_text()))
+            .collect();
+        assert_eq!(
+            sent,
+            [
+                ("/$(service)", "deployed".to_string()),
+                ("/api", "rolled back".to_string())
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+//! Optional HTTP endpoint reporting the health of a running noti.
+use crate::{
+    config::Destination,
+    error::{Error, Result},
+};
+use axum::{extract::State, routing::get, Json, Router};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Counters describing what noti has done so far.
+#[derive(Debug, Default)]
+pub struct Status {
+    lines: AtomicU64,
+    sent: AtomicU64,
+    failed: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    latencies: Mutex<BTreeMap<usize, Latency>>,
+    outcomes: Mutex<Vec<Outcome>>,
+}
+
+/// How a single send to a destination went, as reported by `--output json`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Outcome {
+    /// Position of the destination in the config, counting from 1.
+    pub destination: usize,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub ok: bool,
+    /// HTTP status of the response, if the send failed because of one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Everything noti did, printed with `--output json`.
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    dispatches: &'a [Outcome],
+}
+
+/// How long sends to a single destination have taken.
+#[derive(Debug, Default, Clone, Copy)]
+struct Latency {
+    count: u32,
+    total: Duration,
+    max: Duration,
+}
+
+/// Point-in-time copy of [`Status`], as served over HTTP.
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    lines: u64,
+    sent: u64,
+    failed: u64,
+    last_error: Option<String>,
+}
+
+impl Status {
+    /// Record a line read from the input stream.
+    pub fn record_line(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the outcome of dispatching a message.
+    pub fn record_dispatch<T>(&self, result: &Result<T>) {
+        match result {
+            Ok(_) => {
+                self.sent.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                *self.last_error.lock().unwrap() = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Record how long a send to the destination at index `destination` took.
+    pub fn record_latency(&self, destination: usize, elapsed: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let latency = latencies.entry(destination).or_default();
+        latency.count += 1;
+        latency.total += elapsed;
+        latency.max = latency.max.max(elapsed);
+    }
+
+    /// Record how a send to `destination`, at index `index`, went.
+    pub fn record_outcome(&self, index: usize, destination: &Destination, result: &Result<()>) {
+        self.outcomes.lock().unwrap().push(Outcome {
+            destination: index + 1,
+            kind: destination.kind(),
+            name: destination.name.clone(),
+            ok: result.is_ok(),
+            status: result.as_ref().err().and_then(Error::http_status),
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+    }
+
+    /// A JSON report of every send and how noti finished, as `result`.
+    pub fn report(&self, result: &Result<()>) -> String {
+        let outcomes = self.outcomes.lock().unwrap();
+        let report = Report {
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(ToString::to_string),
+            dispatches: &outcomes,
+        };
+        serde_json::to_string(&report).expect("Serde serialize for `Report`")
+    }
+
+    /// Average and maximum send latency, one line per destination sent to.
+    pub fn latency_summary(&self, destinations: &[Destination]) -> String {
+        let mut summary = String::new();
+
+        for (&index, latency) in self.latencies.lock().unwrap().iter() {
+            let label = destinations.get(index).map_or_else(
+                || format!("unknown #{}", index + 1),
+                |destination| destination.label(index),
+            );
+            let _ = writeln!(
+                summary,
+                "{label}: avg {}ms, max {}ms over {} send(s)",
+                (latency.total / latency.count).as_millis(),
+                latency.max.as_millis(),
+                latency.count,
+            );
+        }
+
+        summary
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            lines: self.lines.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+async fn get_status(State(status): State<Arc<Status>>) -> Json<Snapshot> {
+    Json(status.snapshot())
+}
+
+/// Serve `status` as JSON on `addr` in the background.
+///
+/// Returns the address actually bound, which differs from `addr`
+/// when binding to port 0.
+pub async fn serve(addr: SocketAddr, status: Arc<Status>) -> Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let app = Router::new().route("/", get(get_status)).with_state(status);
+
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{serve, Status};
+    use crate::{
+        config::Destination,
+        error::{Error, Result},
+    };
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    pub fn latency_summary_per_destination_test() {
+        let status = Status::default();
+        let destinations = [
+            Destination::default_webhook(),
+            Destination {
+                name: Some("popup".into()),
+                ..Destination::default_desktop()
+            },
+        ];
+        status.record_latency(0, Duration::from_millis(100));
+        status.record_latency(0, Duration::from_millis(300));
+        status.record_latency(1, Duration::from_millis(5));
+
+        assert_eq!(
+            status.latency_summary(&destinations),
+            "webhook #1: avg 200ms, max 300ms over 2 send(s)\n\
+             popup: avg 5ms, max 5ms over 1 send(s)\n"
+        );
+    }
+
+    #[test]
+    pub fn report_lists_each_dispatch_test() {
+        let status = Status::default();
+        let webhook = Destination {
+            name: Some("oncall".into()),
+            ..Destination::default_webhook()
+        };
+        status.record_outcome(0, &webhook, &Ok(()));
+        status.record_outcome(1, &Destination::default_desktop(), &Err(Error::Timeout));
+        status.record_outcome(0, &webhook, &Err(Error::Redirected { status: 302 }));
+
+        let report: serde_json::Value =
+            serde_json::from_str(&status.report(&Err(Error::NoMessage))).unwrap();
+        assert_eq!(report["ok"], false);
+        assert_eq!(report["error"], Error::NoMessage.to_string());
+        assert_eq!(
+            report["dispatches"][0],
+            serde_json::json!({"destination": 1, "type": "webhook", "name": "oncall", "ok": true})
+        );
+        assert_eq!(report["dispatches"][1]["type"], "desktop");
+        assert_eq!(report["dispatches"][1]["status"], serde_json::Value::Null);
+        assert_eq!(report["dispatches"][2]["status"], 302);
+        assert_eq!(
+            report["dispatches"][2]["error"],
+            Error::Redirected { status: 302 }.to_string()
+        );
+
+        let report: serde_json::Value =
+            serde_json::from_str(&Status::default().report(&Ok(()))).unwrap();
+        assert_eq!(report, serde_json::json!({"ok": true, "dispatches": []}));
+    }
+
+    #[tokio::test]
+    pub async fn serve_status_test() -> Result<()> {
+        let status = Arc::new(Status::default());
+        status.record_line();
+        status.record_line();
+        status.record_dispatch(&Ok(()));
+        status.record_dispatch::<()>(&Err(Error::NoMessage));
+
+        let addr = serve("127.0.0.1:0".parse().unwrap(), status).await?;
+        let body = reqwest::get(format!("http://{addr}/"))
+            .await?
+            .text()
+            .await?;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+        assert_eq!(json["lines"], 2);
+        assert_eq!(json["sent"], 1);
+        assert_eq!(json["failed"], 1);
+        assert_eq!(json["last_error"], Error::NoMessage.to_string());
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+//! Placeholder substitution for user supplied templates.
+use crate::error::{Error, Result};
+use indexmap::IndexMap;
+use std::{
+    ffi::OsString,
+    io::Read,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// How long a `$(exec:...)` command may run before it is killed.
+const EXEC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Named values captured from input, by name.
+pub type Vars = IndexMap<String, String>;
+
+/// Replace each `$(name)` placeholder in `template` with `lookup(name)`.
+///
+/// Placeholders `lookup` doesn't know about are left as they are.
+pub fn substitute(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("$(") {
+        let Some(len) = rest[start..].find(')') else {
+            break;
+        };
+        let placeholder = &rest[start..start + len + 1];
+
+        output.push_str(&rest[..start]);
+        match lookup(&placeholder[2..placeholder.len() - 1]) {
+            Some(value) => output.push_str(&value),
+            None => output.push_str(placeholder),
+        }
+        rest = &rest[start + len + 1..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Value of a built-in placeholder: `timestamp` (ISO-8601, UTC), `date`, `hostname`,
+/// `loadavg` or `mem`.
+pub fn builtin(name: &str) -> Option<String> {
+    match name {
+        "timestamp" => Some(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+        "date" => Some(chrono::Utc::now().format("%Y-%m-%d").to_string()),
+        "hostname" => Some(gethostname::gethostname().to_string_lossy().into_owned()),
+        "loadavg" => Some(load_average()),
+        "mem" => Some(memory()),
+        _ => None,
+    }
+}
+
+/// The 1, 5 and 15 minute load averages, or empty where the OS has none.
+fn load_average() -> String {
+    if !sysinfo::IS_SUPPORTED_SYSTEM || cfg!(windows) {
+        return String::new();
+    }
+    let load = sysinfo::System::load_average();
+    format!("{:.2} {:.2} {:.2}", load.one, load.five, load.fifteen)
+}
+
+/// Used and total memory like `3.2/15.5 GiB`, or empty if it can't be read.
+fn memory() -> String {
+    const GIB: f64 = (1u64 << 30) as f64;
+
+    let mut system = sysinfo::System::new();
+    system.refresh_memory();
+    match system.total_memory() {
+        0 => String::new(),
+        total => format!(
+            "{:.1}/{:.1} GiB",
+            system.used_memory() as f64 / GIB,
+            total as f64 / GIB
+        ),
+    }
+}
+
+/// Render `template`, substituting `$(message)`, any named `vars` and
+/// [`numeric`] placeholders.
+pub fn render(template: &str, message: &str, vars: &Vars) -> String {
+    substitute(template, |name| match name {
+        "message" => Some(message.to_string()),
+        _ => vars.get(name).cloned().or_else(|| numeric(name, vars)),
+    })
+}
+
+/// Value of a `num:` placeholder, a numeric var with at most one operation
+/// on it, like `num:latency/1000` or `num:count+1`.
+///
+/// Fails if the var isn't a number, or when dividing by zero.
+pub fn numeric(name: &str, vars: &Vars) -> Option<String> {
+    let expression = name.strip_prefix("num:")?;
+    let (var, op, operand) = match expression.find(['+', '-', '*', '/']) {
+        Some(at) => (
+            &expression[..at],
+            expression.as_bytes()[at],
+            expression[at + 1..].trim().parse::<f64>().ok()?,
+        ),
+        None => (expression, b'+', 0.0),
+    };
+    let value = vars.get(var.trim())?.trim().parse::<f64>().ok()?;
+
+    let result = match op {
+        b'+' => value + operand,
+        b'-' => value - operand,
+        b'*' => value * operand,
+        _ if operand == 0.0 => return None,
+        _ => value / operand,
+    };
+    let formatted = format!("{result:.6}");
+    Some(
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string(),
+    )
+}
+
+/// Render a URL template, substituting named `vars` percent-encoded so each
+/// fills at most one path segment.
+pub fn render_url(url: &str, vars: &Vars) -> String {
+    substitute(url, |name| {
+        vars.get(name).map(|value| percent_encode(value))
+    })
+}
+
+/// Percent-encode every byte of `value` other than unreserved URL characters.
+pub fn percent_encode(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                output.push(byte as char)
+            }
+            _ => output.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    output
+}
+
+/// Replace each `${NAME}` in `input` with the value of the environment variable
+/// `NAME`, as looked up by `var`.
+///
+/// `$${NAME}` is left as the literal `${NAME}`. Fails if a variable isn't set,
+/// rather than leaving the placeholder in place. Values which aren't valid UTF-8
+/// have the invalid bytes replaced with U+FFFD.
+pub fn expand_env(input: &str, var: impl Fn(&str) -> Option<OsString>) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            output.push_str(&rest[..start - 1]);
+            output.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + len];
+
+        output.push_str(&rest[..start]);
+        match var(name) {
+            Some(value) => output.push_str(&value.to_string_lossy()),
+            None => return Err(Error::MissingEnvVar { name: name.into() }),
+        }
+        rest = &rest[start + len + 1..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Run `command` for an `$(exec:command)` placeholder, returning its trimmed stdout.
+///
+/// The command is split on whitespace and run directly rather than through a
+/// shell, with no stdin and only `PATH` and `HOME` from the environment. It is
+/// killed if it runs for longer than `EXEC_TIMEOUT`. Returns `None` if the
+/// command can't be run, fails, or times out.
+pub fn exec(command: &str) -> Option<String> {
+    let mut args = command.split_whitespace();
+    let mut child = Command::new(args.next()?)
+        .args(args)
+        .env_clear()
+        .envs(std::env::vars_os().filter(|(name, _)| name == "PATH" || name == "HOME"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    // Read on another thread so a chatty command can't fill the pipe and stall.
+    let mut stdout = child.stdout.take()?;
+    let reader = std::thread::spawn(move || {
+        let mut output = vec![];
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait().ok()? {
+            Some(status) => break status,
+            None if start.elapsed() > EXEC_TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    };
+
+    let output = reader.join().ok()?.ok()?;
+    match status.success() {
+        true => Some(String::from_utf8_lossy(&output).trim_end().to_string()),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{builtin, expand_env, numeric, render, Vars};
+    use crate::error::{Error, Result};
+    use std::ffi::OsString;
+
+    #[test]
+    pub fn render_substitutes_known_placeholders_test() {
+        let vars = Vars::from([("level".to_string(), "ERROR".to_string())]);
+
+        assert_eq!(
+            render("[$(level)] $(message) $(unknown) $(", "disk full", &vars),
+            "[ERROR] disk full $(unknown) $("
+        );
+    }
+
+    #[test]
+    pub fn numeric_placeholders_test() {
+        let vars = Vars::from([
+            ("latency".to_string(), "1530".to_string()),
+            ("host".to_string(), "db1".to_string()),
+        ]);
+
+        assert_eq!(render("took $(num:latency/1000)s", "", &vars), "took 1.53s");
+        assert_eq!(numeric("num:latency * 2", &vars).as_deref(), Some("3060"));
+        assert_eq!(numeric("num:latency-30", &vars).as_deref(), Some("1500"));
+        assert_eq!(numeric("num:latency", &vars).as_deref(), Some("1530"));
+        assert_eq!(numeric("num:latency/0", &vars), None);
+        assert_eq!(numeric("num:host/2", &vars), None);
+        assert_eq!(numeric("num:latency/x", &vars), None);
+        assert_eq!(numeric("latency", &vars), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    pub fn system_placeholders_test() {
+        let loadavg = builtin("loadavg").unwrap();
+        let mem = builtin("mem").unwrap();
+
+        assert_eq!(loadavg.split(' ').count(), 3);
+        assert!(loadavg.split(' ').all(|load| load.parse::<f64>().is_ok()));
+        assert!(mem.ends_with(" GiB") && mem.contains('/'));
+    }
+
+    #[test]
+    pub fn expand_env_substitutes_variables_test() -> Result<()> {
+        let var = |name: &str| (name == "TOKEN").then(|| OsString::from("s3cret"));
+
+        assert_eq!(
+            expand_env("https://example.com/${TOKEN}?q=$${LITERAL}", var)?,
+            "https://example.com/s3cret?q=${LITERAL}"
+        );
+        assert!(expand_env("${UNSET}", var).is_err_and(|e| matches!(
+            e,
+            Error::MissingEnvVar { name } if name == "UNSET"
+        )));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn expand_env_substitutes_invalid_utf8_test() -> Result<()> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let var = |_: &str| Some(OsStr::from_bytes(b"bad \xff").to_os_string());
+
+        assert_eq!(expand_env("${INVALID}", var)?, "bad \u{FFFD}");
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+//! Suppression of repeated notifications across separate noti processes.
+use crate::{config::Throttle, error::Result};
+use fs2::FileExt;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A successful [`claim`], which can be given back if sending failed.
+#[derive(Debug)]
+pub struct Claim {
+    path: PathBuf,
+    previous: String,
+    claimed: String,
+}
+
+impl Claim {
+    /// Restore the state file to how it was before the claim, so the next
+    /// attempt isn't throttled. Left alone if another claim has since been made.
+    pub fn release(self) -> Result<()> {
+        let mut file = open(&self.path)?;
+        file.lock_exclusive()?;
+        let mut current = String::new();
+        file.read_to_string(&mut current)?;
+        if current == self.claimed {
+            overwrite(&mut file, &self.previous)?;
+        }
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?)
+}
+
+fn overwrite(file: &mut File, contents: &str) -> Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    write!(file, "{contents}")?;
+    Ok(())
+}
+
+/// Stable FNV-1a hash of `key`, used to name its state file.
+fn hash(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Claim the right to send `key` now.
+///
+/// Returns `None` if `key` was already claimed within the throttle window.
+/// Each key's state file is locked while it is checked and updated, so of
+/// several processes claiming the same key at once only one succeeds.
+pub fn claim(throttle: &Throttle, key: &str) -> Result<Option<Claim>> {
+    let dir = throttle.state_dir();
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(format!("{:016x}", hash(key)));
+    let mut file = open(&path)?;
+    file.lock_exclusive()?;
+
+    let mut last = String::new();
+    file.read_to_string(&mut last)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    if let Ok(last) = last.trim().parse::<u64>() {
+        if now.saturating_sub(Duration::from_secs(last)).as_secs() < throttle.window_secs {
+            return Ok(None);
+        }
+    }
+
+    let claimed = now.as_secs().to_string();
+    overwrite(&mut file, &claimed)?;
+
+    Ok(Some(Claim {
+        path,
+        previous: last,
+        claimed,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::claim;
+    use crate::{config::Throttle, error::Result, mock::TempDir};
+
+    #[test]
+    pub fn claim_within_window_test() -> Result<()> {
+        let dir = TempDir::new("claim_within_window_test")?;
+        let state_dir = dir.join("state");
+        let throttle = |window_secs| Throttle {
+            window_secs,
+            state_dir: Some(state_dir.clone()),
+        };
+
+        assert!(claim(&throttle(60), "disk full")?.is_some());
+        assert!(claim(&throttle(60), "disk full")?.is_none());
+        assert!(claim(&throttle(60), "disk ok")?.is_some());
+        assert!(claim(&throttle(0), "disk full")?.is_some());
+
+        claim(&throttle(60), "disk low")?.unwrap().release()?;
+        assert!(claim(&throttle(60), "disk low")?.is_some());
+        Ok(())
+    }
+}